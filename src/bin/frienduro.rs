@@ -0,0 +1,1043 @@
+extern crate chrono;
+extern crate frienduro;
+extern crate getopts;
+extern crate postgres;
+extern crate serde_json;
+
+use self::frienduro::compare::DEFAULT_SECTORS;
+use self::frienduro::duplicate::DuplicatePolicy;
+use self::frienduro::export::{export_results, ResultsFormat};
+use self::frienduro::formats::{detect_format, read_track};
+use self::frienduro::manifest::{export_manifest, import_manifest};
+use self::frienduro::sectors::{SectorLeader, SectorPosition};
+use self::frienduro::sensors::SensorSummary;
+use self::frienduro::simplify::SimplifyOptions;
+use self::frienduro::speed::SpeedStats;
+use self::frienduro::timing::{EventTiming, TimingMode, DEFAULT_LATE_PENALTY};
+use self::frienduro::track::MergedTrack;
+use self::frienduro::*;
+use chrono::prelude::*;
+use getopts::{Matches, Options};
+use postgres::Connection;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+const COMMANDS: &'static str = "Commands:
+    db migrate                              apply pending database migrations
+    db reset                                drop all data and recreate the database
+    user add NAME EMAIL                     create a user
+    user list                               list users
+    segment add FILE [NAME]                 create a segment from a track or line file,
+                                            optionally with --tolerance
+    segment list                            list segments
+    segment show ID                         show a segment with its events and leaderboard
+    segment split ID DISTANCE|LAT,LON [NAME]
+                                            add a split point to a segment, in meters
+                                            from its start or closest to a position
+    segment unsplit SPLIT_ID                remove a split point from its segment
+    segment efforts ID                      list every timed ride of a segment
+    segment leaderboard ID [COUNT]          list the personal bests on a segment
+    segment compare ID PARTICIPATION_ID PARTICIPATION_ID [SECTORS]
+                                            show where the second rider lost time to
+                                            the first on a segment
+    event create NAME [SEGMENT_ID...]       create an event, optionally with --date
+    event add-segment EVENT_ID SEGMENT_ID   add a segment to an event as its last stage
+                                            and retime it
+    event name-stage EVENT_ID SEGMENT_ID [NAME]
+                                            name a stage, or clear its name
+    event in-order EVENT_ID yes|no          require stages to be ridden in order
+    event duplicates EVENT_ID POLICY        choose which participation of a rider counts,
+                                            one of reject, replace and keep-best
+    event timing EVENT_ID MODE              choose how totals are computed, one of stages,
+                                            elapsed and liaison, optionally with --start
+                                            and --penalty
+    event cutoff EVENT_ID SEGMENT_ID [SECONDS]
+                                            limit the time to reach a stage, or clear
+                                            the limit
+    event window EVENT_ID [OPEN] [CLOSE]    only count attempts started between two
+                                            RFC3339 times, - leaves an end open
+    event import PATH                       import an event from a manifest file, a
+                                            directory with an event.toml manifest or a
+                                            directory with segments/ and users/
+    event export ID FILE                    write a manifest of an event with its tracks
+    event list                              list events
+    event show ID                           show an event with its results
+    participation add EVENT_ID USER_ID FILE upload a track to an event
+    activity add USER_ID FILE               upload a ride outside of any event, timed on
+                                            every segment it passes through
+    activity list USER_ID                   list the activities of a user
+    activity show ID                        show an activity with its efforts
+    participation retime ID                 recompute timing for a participation,
+                                            or an event or segment with --event/--segment
+    participation delete ID                 delete a participation
+    participation reprocess ID              rebuild a participation from its uploaded file,
+                                            or all with --all or an event with --event
+    participation bench EVENT_ID USER_ID FILE
+                                            compare matching times of a track with and
+                                            without simplification, storing nothing,
+                                            optionally saved as JSON with --output
+    segment reprocess ID                    rebuild a segment from its uploaded file
+    upload show ID                          show an uploaded file
+    upload get ID FILE                      write an uploaded file back to disk
+    results show EVENT_ID                   print the results of an event
+    results export EVENT_ID                 export results, see --format and --output
+";
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} [options] COMMAND SUBCOMMAND [ARGS...]", program);
+    print!("{}\n{}", opts.usage(&brief), COMMANDS);
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn arg<T: FromStr>(matches: &Matches, index: usize, name: &str) -> T {
+    match matches.free.get(index).map(|a| a.parse::<T>()) {
+        Some(Ok(value)) => value,
+        Some(Err(..)) => fail(&format!("invalid {}: {}", name, matches.free[index])),
+        None => fail(&format!("missing argument {}", name)),
+    }
+}
+
+/// Read a track file, returning its contents along with the track so the file can be stored.
+fn read_track_file(path: &str) -> (Vec<u8>, MergedTrack) {
+    let data = match read_whole_file_bytes(path) {
+        Ok(data) => data,
+        Err(err) => fail(&format!("could not read {}: {}", path, err)),
+    };
+
+    match read_track(&data) {
+        Ok(track) => (data, track),
+        Err(err) => fail(&format!("could not read {}: {}", path, err)),
+    }
+}
+
+fn file_name(path: &str) -> Option<&str> {
+    Path::new(path).file_name().and_then(|name| name.to_str())
+}
+
+fn simplify_options(matches: &Matches) -> SimplifyOptions {
+    if matches.opt_present("simplify") {
+        SimplifyOptions::simplified()
+    } else {
+        SimplifyOptions::default()
+    }
+}
+
+fn add_participation(
+    db: &Connection,
+    event_id: i64,
+    user_id: i64,
+    track: &MergedTrack,
+    options: &SimplifyOptions,
+    upload: &UploadFile,
+) {
+    for gap in &track.gaps {
+        println!(
+            "\tgap of {}s and {:.0}m before point {}",
+            gap.seconds, gap.meters, gap.index
+        );
+    }
+
+    match create_participation(db, event_id, user_id, &track.points, options, Some(upload)) {
+        Ok(participation_id) => println!("\tadded participation {}", participation_id),
+        Err(Error::Invalid(report)) => {
+            for issue in report.issues {
+                println!("\trejected: {} ({} times)", issue.message, issue.count);
+            }
+        }
+        Err(Error::Duplicate(duplicate)) => println!("\trejected: {}", duplicate),
+        Err(err) => fail(&err.to_string()),
+    }
+}
+
+fn print_efforts(efforts: &[SegmentEffort]) {
+    for (i, effort) in efforts.iter().enumerate() {
+        println!(
+            "{}\t{}\t{}s\t{}\t{}",
+            i + 1,
+            effort.username,
+            effort.elapsed,
+            effort
+                .event_name
+                .as_ref()
+                .map(|name| name.as_str())
+                .unwrap_or("activity"),
+            effort.started_at.map(|t| t.to_string()).unwrap_or_default()
+        );
+    }
+}
+
+/// Format the sensor readings of a stage attempt, empty if nothing was recorded.
+fn format_sensors(sensors: &SensorSummary) -> String {
+    let mut parts = Vec::new();
+    if let (Some(avg), Some(max)) = (sensors.avg_heart_rate, sensors.max_heart_rate) {
+        parts.push(format!("HR {:.0}/{} bpm", avg, max));
+    }
+    if let Some(cadence) = sensors.avg_cadence {
+        parts.push(format!("{:.0} rpm", cadence));
+    }
+    if let Some(temperature) = sensors.avg_temperature {
+        parts.push(format!("{:.1} °C", temperature));
+    }
+    parts.join(", ")
+}
+
+/// Format the speed of a stage attempt in km/h, empty if it was not computed.
+fn format_speed(speed: &Option<SpeedStats>) -> String {
+    match speed {
+        Some(speed) => format!(
+            "{:.2}km {:.1}/{:.1} km/h, stopped {:.0}s",
+            speed.distance / 1000.0,
+            speed.avg_speed * 3.6,
+            speed.max_speed * 3.6,
+            speed.stopped_seconds
+        ),
+        None => String::new(),
+    }
+}
+
+fn print_results(db: &Connection, event_id: i64) {
+    let results = get_event_results(db, event_id);
+    for (i, result) in results.iter().enumerate() {
+        match result.time {
+            0.0 => println!("{} - {} DNF", i + 1, result.username),
+            time => match result.penalty {
+                Some(penalty) if penalty > 0.0 => println!(
+                    "{} - {} {}s (penalty {}s)",
+                    i + 1,
+                    result.username,
+                    time,
+                    penalty
+                ),
+                _ => println!("{} - {} {}s", i + 1, result.username, time),
+            },
+        }
+        for stage in &result.stages {
+            match (stage.elapsed, stage.started_at, stage.finished_at) {
+                (Some(elapsed), Some(started_at), Some(finished_at)) => println!(
+                    "\t{} {}s ({} - {}) {} {}",
+                    stage.name,
+                    elapsed,
+                    started_at,
+                    finished_at,
+                    format_speed(&stage.speed),
+                    format_sensors(&stage.sensors)
+                ),
+                _ => println!("\t{} -", stage.name),
+            }
+            if let Some(ref sectors) = stage.sectors {
+                let times = sectors
+                    .iter()
+                    .map(|seconds| format!("{:.1}s", seconds))
+                    .collect::<Vec<String>>();
+                println!("\t\tsectors: {}", times.join(" "));
+            }
+        }
+    }
+}
+
+fn print_sector_leaders(leaders: &[SectorLeader]) {
+    for leader in leaders {
+        println!(
+            "\t{}\t{}\t{}\t{:.1}s",
+            leader.segment_id,
+            leader.sector + 1,
+            leader.username,
+            leader.elapsed
+        );
+    }
+}
+
+/// Parse where to split a segment, a distance in meters or a LAT,LON position.
+fn parse_sector_position(value: &str) -> SectorPosition {
+    let parts = value
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>();
+    match parts.as_ref().map(|parts| parts.as_slice()) {
+        Ok([distance]) => SectorPosition::Distance(*distance),
+        Ok([lat, lon]) => SectorPosition::Point {
+            lat: *lat,
+            lon: *lon,
+        },
+        _ => fail(&format!("invalid sector position {}", value)),
+    }
+}
+
+/// List the entries of a directory, sorted by file name.
+fn read_dir_sorted(path: &Path) -> Vec<fs::DirEntry> {
+    let mut entries =
+        match fs::read_dir(path).and_then(|entries| entries.collect::<Result<Vec<_>, _>>()) {
+            Ok(entries) => entries,
+            Err(err) => fail(&format!("could not read {}: {}", path.display(), err)),
+        };
+    entries.sort_by_key(|entry| entry.file_name());
+    entries
+}
+
+/// Import an event from a directory with the track files of the segments in `segments/` and one
+/// track file per rider in `users/`, named after the rider.
+fn import_event_dir(db: &Connection, event_dir: &str) -> i64 {
+    let event_path = Path::new(event_dir);
+    let event_name = match event_path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => fail(&format!("invalid event directory {}", event_dir)),
+    };
+    println!("Importing event: {}", event_name);
+
+    // Stages are ordered by file name, use a manifest to order them otherwise
+    let segment_ids = read_dir_sorted(&event_path.join("segments"))
+        .into_iter()
+        .map(|segment_file| {
+            let segment_name = segment_file.file_name().to_string_lossy().into_owned();
+            println!("\tadding segment: {}", segment_name);
+
+            let path = segment_file.path().to_string_lossy().into_owned();
+            let (data, track) = read_track_file(&path);
+            let upload = UploadFile {
+                data: &data,
+                filename: Some(&segment_name),
+                uploader_id: None,
+            };
+
+            match create_segment(
+                db,
+                &segment_name,
+                DEFAULT_TOLERANCE,
+                &track.points,
+                Some(&upload),
+            ) {
+                Ok(segment_id) => segment_id,
+                Err(err) => fail(&format!("could not add segment {}: {}", segment_name, err)),
+            }
+        })
+        .collect::<Vec<i64>>();
+
+    let event_id = match create_event(db, &event_name, None, &segment_ids) {
+        Ok(event_id) => event_id,
+        Err(err) => fail(&format!("could not create event {}: {}", event_name, err)),
+    };
+
+    for user_file in read_dir_sorted(&event_path.join("users")) {
+        let user_path = user_file.path();
+        let filename = user_path.to_string_lossy().into_owned();
+        let data = match read_whole_file_bytes(&filename) {
+            Ok(data) => data,
+            Err(err) => fail(&format!("could not read {}: {}", filename, err)),
+        };
+        // Other files next to the tracks are skipped
+        if detect_format(&data).is_none() {
+            continue;
+        }
+        let track = match read_track(&data) {
+            Ok(track) => track,
+            Err(err) => fail(&format!("could not read {}: {}", filename, err)),
+        };
+
+        let user_name = match user_path.file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => fail(&format!("invalid rider file {}", filename)),
+        };
+        let user = match create_user(db, &user_name, "") {
+            Some(user) => user,
+            None => fail(&format!("could not add user {}", user_name)),
+        };
+        if let Err(err) = register_rider(db, event_id, user.id, None) {
+            fail(&format!("could not register {}: {}", user_name, err));
+        }
+        println!("\tadding user: {}", user_name);
+
+        let upload = UploadFile {
+            data: &data,
+            filename: file_name(&filename),
+            uploader_id: Some(user.id),
+        };
+        add_participation(
+            db,
+            event_id,
+            user.id,
+            &track,
+            &SimplifyOptions::default(),
+            &upload,
+        );
+    }
+
+    event_id
+}
+
+/// Import an event from a manifest, or from a directory holding an `event.toml` manifest.
+fn import_event_manifest(db: &Connection, path: &Path) -> i64 {
+    println!("Importing event manifest: {}", path.display());
+    match import_manifest(db, path) {
+        Ok(summary) => {
+            for (rider, report) in summary.rejected {
+                for issue in report.issues {
+                    println!(
+                        "\trejected {}: {} ({} times)",
+                        rider, issue.message, issue.count
+                    );
+                }
+            }
+            summary.event_id
+        }
+        Err(err) => fail(&format!("could not import {}: {}", path.display(), err)),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("", "date", "date of a created event", "YYYY-MM-DD");
+    opts.optopt(
+        "",
+        "tolerance",
+        "distance in meters a track may stray from a created segment",
+        "METERS",
+    );
+    opts.optflag(
+        "",
+        "event",
+        "retime all participations in the event with the given ID",
+    );
+    opts.optflag(
+        "",
+        "segment",
+        "retime all participations touching the segment with the given ID",
+    );
+    opts.optopt(
+        "f",
+        "format",
+        "format of exported results, one of csv, json and html",
+        "FORMAT",
+    );
+    opts.optopt(
+        "o",
+        "output",
+        "write exported results or benchmarks to a file",
+        "FILE",
+    );
+    opts.optflag("", "all", "reprocess all participations");
+    opts.optopt(
+        "",
+        "start",
+        "mass start time of an event, the clock starts here for every rider",
+        "RFC3339",
+    );
+    opts.optopt(
+        "",
+        "penalty",
+        "seconds added per started minute late at a stage",
+        "SECONDS",
+    );
+    opts.optflag(
+        "",
+        "simplify",
+        "drop redundant points of uploaded tracks before storing them",
+    );
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => fail(&f.to_string()),
+    };
+    if matches.opt_present("h") || matches.free.len() < 2 {
+        print_usage(&program, &opts);
+        return;
+    }
+
+    let db = establish_connection();
+
+    let command = (matches.free[0].as_str(), matches.free[1].as_str());
+    match command {
+        ("db", "migrate") => {
+            let count = match migrate_db(&db) {
+                Ok(count) => count,
+                Err(err) => fail(&format!("could not migrate the database: {}", err)),
+            };
+            println!("Applied {} migrations", count);
+        }
+        ("db", "reset") => {
+            if let Err(err) = create_db(&db) {
+                fail(&format!("could not recreate the database: {}", err));
+            }
+            println!("Database recreated");
+        }
+        ("user", "add") => {
+            let name: String = arg(&matches, 2, "NAME");
+            let email: String = arg(&matches, 3, "EMAIL");
+            let user = match create_user(&db, &name, &email) {
+                Some(user) => user,
+                None => fail(&format!("could not add user {}", name)),
+            };
+            println!("{}", user.id);
+        }
+        ("user", "list") => {
+            for user in get_users(&db).unwrap() {
+                println!("{}\t{}\t{}", user.id, user.name, user.email);
+            }
+        }
+        ("segment", "add") => {
+            let file: String = arg(&matches, 2, "FILE");
+            let name = match matches.free.get(3) {
+                Some(name) => name.clone(),
+                None => Path::new(&file)
+                    .file_stem()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let tolerance = match matches.opt_str("tolerance") {
+                Some(tolerance) => match tolerance.parse() {
+                    Ok(tolerance) => tolerance,
+                    Err(..) => fail(&format!("invalid tolerance: {}", tolerance)),
+                },
+                None => DEFAULT_TOLERANCE,
+            };
+            let (data, track) = read_track_file(&file);
+            let upload = UploadFile {
+                data: &data,
+                filename: file_name(&file),
+                uploader_id: None,
+            };
+            let segment_id =
+                match create_segment(&db, &name, tolerance, &track.points, Some(&upload)) {
+                    Ok(segment_id) => segment_id,
+                    Err(err) => fail(&format!("could not add segment {}: {}", name, err)),
+                };
+            println!("{}", segment_id);
+        }
+        ("segment", "list") => {
+            for segment in get_segments(&db).unwrap() {
+                println!("{}\t{}\t{:.0}m", segment.id, segment.name, segment.length);
+            }
+        }
+        ("segment", "show") => {
+            let segment_id: i64 = arg(&matches, 2, "ID");
+            match get_segment_details(&db, segment_id) {
+                Some(details) => {
+                    let segment = details.segment;
+                    println!("id: {}", segment.id);
+                    println!("name: {}", segment.name);
+                    println!("length: {:.0}m", segment.length);
+                    println!("tolerance: {:.0}m", segment.tolerance);
+                    if let Some(climb) = segment.climb {
+                        println!(
+                            "elevation: {:.0}m - {:.0}m, +{:.0}m/-{:.0}m, {:.1}%",
+                            climb.min_elevation,
+                            climb.max_elevation,
+                            climb.ascent,
+                            climb.descent,
+                            climb.avg_gradient
+                        );
+                    }
+                    println!("events:");
+                    for event in &details.events {
+                        println!("\t{}\t{}", event.id, event.name);
+                    }
+                    if !details.sectors.is_empty() {
+                        println!("split points:");
+                        for sector in &details.sectors {
+                            println!(
+                                "\t{}\t{:.0}m\t{}",
+                                sector.id,
+                                sector.distance,
+                                sector.name.as_ref().map(|n| n.as_str()).unwrap_or("")
+                            );
+                        }
+                    }
+                    println!("leaderboard:");
+                    print_efforts(&details.leaderboard);
+                    if !details.sector_leaders.is_empty() {
+                        println!("sector leaders:");
+                        print_sector_leaders(&details.sector_leaders);
+                    }
+                }
+                None => fail(&format!("no segment with id {}", segment_id)),
+            }
+        }
+        ("segment", "split") => {
+            let segment_id: i64 = arg(&matches, 2, "ID");
+            let position: String = arg(&matches, 3, "POSITION");
+            let name = matches.free.get(4).map(|name| name.as_str());
+            match add_segment_sector(&db, segment_id, name, parse_sector_position(&position)) {
+                Ok(sector_id) => println!("{}", sector_id),
+                Err(err) => fail(&err.to_string()),
+            }
+        }
+        ("segment", "unsplit") => {
+            let sector_id: i64 = arg(&matches, 2, "SPLIT_ID");
+            if !delete_segment_sector(&db, sector_id).unwrap() {
+                fail(&format!("no split point with id {}", sector_id));
+            }
+        }
+        ("segment", "efforts") => {
+            let segment_id: i64 = arg(&matches, 2, "ID");
+            print_efforts(&get_segment_efforts(&db, segment_id));
+        }
+        ("segment", "leaderboard") => {
+            let segment_id: i64 = arg(&matches, 2, "ID");
+            let limit = matches
+                .free
+                .get(3)
+                .map(|_| arg::<i64>(&matches, 3, "COUNT"));
+            print_efforts(&get_segment_leaderboard(&db, segment_id, limit));
+        }
+        ("segment", "compare") => {
+            let segment_id: i64 = arg(&matches, 2, "ID");
+            let a: i64 = arg(&matches, 3, "PARTICIPATION_ID");
+            let b: i64 = arg(&matches, 4, "PARTICIPATION_ID");
+            let sectors = matches
+                .free
+                .get(5)
+                .map_or(DEFAULT_SECTORS, |_| arg::<usize>(&matches, 5, "SECTORS"));
+            let comparison = match compare_attempts(&db, segment_id, a, b, sectors).unwrap() {
+                Some(comparison) => comparison,
+                None => fail(&format!(
+                    "participations {} and {} are not both timed on segment {}",
+                    a, b, segment_id
+                )),
+            };
+            for sector in &comparison.sectors {
+                println!(
+                    "{:.0}m - {:.0}m\t{:.1}s\t{:.1}s\t{:+.1}s",
+                    sector.start, sector.end, sector.seconds_a, sector.seconds_b, sector.delta
+                );
+            }
+            if let Some(last) = comparison.gaps.last() {
+                println!("total\t{:+.1}s", last.gap);
+            }
+        }
+        ("event", "create") => {
+            let name: String = arg(&matches, 2, "NAME");
+            let date = matches.opt_str("date").map(|date| {
+                match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+                    Ok(date) => date,
+                    Err(err) => fail(&format!("invalid date {}: {}", date, err)),
+                }
+            });
+            let segment_ids = (3..matches.free.len())
+                .map(|i| arg(&matches, i, "SEGMENT_ID"))
+                .collect::<Vec<i64>>();
+            let event_id = match create_event(&db, &name, date, &segment_ids) {
+                Ok(event_id) => event_id,
+                Err(err) => fail(&format!("could not create event {}: {}", name, err)),
+            };
+            println!("{}", event_id);
+        }
+        ("event", "add-segment") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let segment_id: i64 = arg(&matches, 3, "SEGMENT_ID");
+            if let Err(err) = add_event_segment(&db, event_id, segment_id) {
+                fail(&format!(
+                    "could not add segment {} to event {}: {}",
+                    segment_id, event_id, err
+                ));
+            }
+        }
+        ("event", "name-stage") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let segment_id: i64 = arg(&matches, 3, "SEGMENT_ID");
+            let name = matches.free.get(4).map(|name| name.as_str());
+            if !set_stage_name(&db, event_id, segment_id, name).unwrap() {
+                fail(&format!(
+                    "segment {} is not a stage of event {}",
+                    segment_id, event_id
+                ));
+            }
+        }
+        ("event", "in-order") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let in_order = match arg::<String>(&matches, 3, "yes|no").as_str() {
+                "yes" => true,
+                "no" => false,
+                other => fail(&format!("expected yes or no, got {}", other)),
+            };
+            set_stages_in_order(&db, event_id, in_order).unwrap();
+        }
+        ("event", "duplicates") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let name: String = arg(&matches, 3, "POLICY");
+            let policy = match DuplicatePolicy::from_name(&name) {
+                Some(policy) => policy,
+                None => fail(&format!("unknown duplicate policy {}", name)),
+            };
+            set_duplicate_policy(&db, event_id, policy).unwrap();
+        }
+        ("event", "timing") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let name: String = arg(&matches, 3, "MODE");
+            let mode = match TimingMode::from_name(&name) {
+                Some(mode) => mode,
+                None => fail(&format!("unknown timing mode {}", name)),
+            };
+            let mass_start =
+                matches
+                    .opt_str("start")
+                    .map(|start| match DateTime::parse_from_rfc3339(&start) {
+                        Ok(start) => start.with_timezone(&Utc),
+                        Err(err) => fail(&format!("invalid start time {}: {}", start, err)),
+                    });
+            let late_penalty = matches
+                .opt_str("penalty")
+                .map_or(DEFAULT_LATE_PENALTY, |penalty| match penalty.parse() {
+                    Ok(penalty) => penalty,
+                    Err(_) => fail(&format!("invalid penalty {}", penalty)),
+                });
+            let timing = EventTiming {
+                mode,
+                mass_start,
+                late_penalty,
+            };
+            if !set_event_timing(&db, event_id, &timing).unwrap() {
+                fail(&format!("no event with id {}", event_id));
+            }
+        }
+        ("event", "cutoff") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let segment_id: i64 = arg(&matches, 3, "SEGMENT_ID");
+            let cutoff = matches
+                .free
+                .get(4)
+                .map(|_| arg::<f64>(&matches, 4, "SECONDS"));
+            if !set_liaison_cutoff(&db, event_id, segment_id, cutoff).unwrap() {
+                fail(&format!(
+                    "segment {} is not a stage of event {}",
+                    segment_id, event_id
+                ));
+            }
+        }
+        ("event", "window") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let time_arg = |i: usize| match matches.free.get(i).map(|s| s.as_str()) {
+                None | Some("-") => None,
+                Some(time) => match DateTime::parse_from_rfc3339(time) {
+                    Ok(time) => Some(time.with_timezone(&Utc)),
+                    Err(err) => fail(&format!("invalid time {}: {}", time, err)),
+                },
+            };
+            let window = EventWindow {
+                open: time_arg(3),
+                close: time_arg(4),
+            };
+            if !set_event_window(&db, event_id, &window).unwrap() {
+                fail(&format!("no event with id {}", event_id));
+            }
+        }
+        ("event", "import") => {
+            let path: String = arg(&matches, 2, "PATH");
+            let path = Path::new(&path);
+            let event_id = if path.is_file() {
+                import_event_manifest(&db, path)
+            } else if path.join("event.toml").is_file() {
+                import_event_manifest(&db, &path.join("event.toml"))
+            } else {
+                import_event_dir(&db, &path.to_string_lossy())
+            };
+            println!("{}", event_id);
+        }
+        ("event", "export") => {
+            let event_id: i64 = arg(&matches, 2, "ID");
+            let file: String = arg(&matches, 3, "FILE");
+            if let Err(err) = export_manifest(&db, event_id, Path::new(&file)) {
+                fail(&format!("could not export {}: {}", file, err));
+            }
+        }
+        ("event", "list") => {
+            for event in get_events(&db).unwrap() {
+                println!("{}\t{}", event.id, event.name);
+            }
+        }
+        ("event", "show") => {
+            let event_id: i64 = arg(&matches, 2, "ID");
+            let event = match get_event(&db, event_id) {
+                Some(event) => event,
+                None => fail(&format!("no event with id {}", event_id)),
+            };
+            println!("id: {}", event.id);
+            println!("name: {}", event.name);
+            if let Some(date) = event.date {
+                println!("date: {}", date);
+            }
+            println!("duplicates: {}", event.duplicate_policy.name());
+            println!("timing: {}", event.timing.mode.name());
+            if let Some(mass_start) = event.timing.mass_start {
+                println!("mass start: {}", mass_start);
+            }
+            if event.timing.mode == TimingMode::Liaison {
+                println!("late penalty: {}s per minute", event.timing.late_penalty);
+            }
+            if let Some(open) = event.start_window_open {
+                println!("window opens: {}", open);
+            }
+            if let Some(close) = event.start_window_close {
+                println!("window closes: {}", close);
+            }
+            if event.stages_in_order {
+                println!("stages (in order):");
+            } else {
+                println!("stages:");
+            }
+            for stage in &event.stages {
+                println!(
+                    "\t{}\t{}\t{}\t{}",
+                    stage.index + 1,
+                    stage.segment_id,
+                    stage.name,
+                    stage
+                        .liaison_cutoff
+                        .map(|cutoff| format!("cutoff {}s", cutoff))
+                        .unwrap_or_default()
+                );
+            }
+            let categories = get_event_categories(&db, event_id);
+            if !categories.is_empty() {
+                println!("categories: {}", categories.join(", "));
+            }
+            println!("riders:");
+            for rider in get_event_riders(&db, event_id) {
+                println!(
+                    "\t{}\t{}\t{}",
+                    rider.user.id,
+                    rider.user.name,
+                    rider.category.unwrap_or_default()
+                );
+            }
+            println!("results:");
+            print_results(&db, event_id);
+            if !event.sector_leaders.is_empty() {
+                println!("sector leaders:");
+                print_sector_leaders(&event.sector_leaders);
+            }
+        }
+        ("participation", "add") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let user_id: i64 = arg(&matches, 3, "USER_ID");
+            let file: String = arg(&matches, 4, "FILE");
+            let (data, track) = read_track_file(&file);
+            let upload = UploadFile {
+                data: &data,
+                filename: file_name(&file),
+                uploader_id: Some(user_id),
+            };
+            add_participation(
+                &db,
+                event_id,
+                user_id,
+                &track,
+                &simplify_options(&matches),
+                &upload,
+            );
+        }
+        ("activity", "add") => {
+            let user_id: i64 = arg(&matches, 2, "USER_ID");
+            let file: String = arg(&matches, 3, "FILE");
+            let (data, track) = read_track_file(&file);
+            let upload = UploadFile {
+                data: &data,
+                filename: file_name(&file),
+                uploader_id: Some(user_id),
+            };
+            match create_activity(
+                &db,
+                user_id,
+                &track.points,
+                &simplify_options(&matches),
+                Some(&upload),
+            ) {
+                Ok(activity_id) => println!("{}", activity_id),
+                Err(Error::Invalid(report)) => {
+                    for issue in report.issues {
+                        println!("rejected: {} ({} times)", issue.message, issue.count);
+                    }
+                    process::exit(1);
+                }
+                Err(Error::Duplicate(duplicate)) => fail(&format!("rejected: {}", duplicate)),
+                Err(err) => fail(&err.to_string()),
+            }
+        }
+        ("activity", "list") => {
+            let user_id: i64 = arg(&matches, 2, "USER_ID");
+            for activity in get_user_activities(&db, user_id) {
+                println!(
+                    "{}\t{}\t{} efforts",
+                    activity.id,
+                    activity
+                        .start_time
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                    activity.efforts.len()
+                );
+            }
+        }
+        ("activity", "show") => {
+            let activity_id: i64 = arg(&matches, 2, "ID");
+            let activity = match get_activity(&db, activity_id) {
+                Some(activity) => activity,
+                None => fail(&format!("no activity with id {}", activity_id)),
+            };
+            println!("id: {}", activity.id);
+            println!("user: {}", activity.user_id);
+            if let Some(start_time) = activity.start_time {
+                println!("start: {}", start_time);
+            }
+            println!("efforts:");
+            for effort in &activity.efforts {
+                println!(
+                    "\t{}\t{}\t{}s\t{}",
+                    effort.segment_id,
+                    effort.name,
+                    effort.elapsed.unwrap_or_default(),
+                    format_sensors(&effort.sensors)
+                );
+            }
+        }
+        ("participation", "retime") => {
+            let id: i64 = arg(&matches, 2, "ID");
+            if matches.opt_present("event") {
+                let count = recompute_event(&db, id).unwrap();
+                println!("Retimed {} participations in event {}", count, id);
+            } else if matches.opt_present("segment") {
+                let count = recompute_segment(&db, id).unwrap();
+                println!("Retimed {} participations using segment {}", count, id);
+            } else {
+                if !recompute_participation(&db, id).unwrap() {
+                    fail(&format!("no participation with id {}", id));
+                }
+                println!("Retimed participation {}", id);
+            }
+        }
+        ("participation", "delete") => {
+            let participation_id: i64 = arg(&matches, 2, "ID");
+            if !delete_participation(&db, participation_id).unwrap() {
+                fail(&format!("no participation with id {}", participation_id));
+            }
+        }
+        ("participation", "reprocess") => {
+            let options = simplify_options(&matches);
+            let result = if matches.opt_present("all") {
+                reprocess_participations(&db, None, &options)
+            } else if matches.opt_present("event") {
+                let event_id: i64 = arg(&matches, 2, "ID");
+                reprocess_participations(&db, Some(event_id), &options)
+            } else {
+                let participation_id: i64 = arg(&matches, 2, "ID");
+                reprocess_participation(&db, participation_id, &options).map(|found| {
+                    if !found {
+                        fail(&format!(
+                            "participation {} has no uploaded file",
+                            participation_id
+                        ));
+                    }
+                    1
+                })
+            };
+            match result {
+                Ok(count) => println!("Reprocessed {} participations", count),
+                Err(err) => fail(&err.to_string()),
+            }
+        }
+        ("segment", "reprocess") => {
+            let segment_id: i64 = arg(&matches, 2, "ID");
+            match reprocess_segment(&db, segment_id) {
+                Ok(true) => println!("Reprocessed segment {}", segment_id),
+                Ok(false) => fail(&format!("segment {} has no uploaded file", segment_id)),
+                Err(err) => fail(&err.to_string()),
+            }
+        }
+        ("upload", "show") => {
+            let upload_id: i64 = arg(&matches, 2, "ID");
+            let upload = match get_upload(&db, upload_id) {
+                Some(upload) => upload,
+                None => fail(&format!("no upload with id {}", upload_id)),
+            };
+            println!("id: {}", upload.id);
+            println!("sha256: {}", upload.sha256);
+            if let Some(filename) = upload.filename {
+                println!("filename: {}", filename);
+            }
+            println!("uploaded at: {}", upload.uploaded_at);
+            if let Some(uploader_id) = upload.uploader_id {
+                println!("uploader: {}", uploader_id);
+            }
+            println!("size: {} bytes", upload.size);
+        }
+        ("upload", "get") => {
+            let upload_id: i64 = arg(&matches, 2, "ID");
+            let file: String = arg(&matches, 3, "FILE");
+            match get_upload_data(&db, upload_id) {
+                Some(data) => fs::write(&file, data).unwrap(),
+                None => fail(&format!("no upload with id {}", upload_id)),
+            }
+        }
+        ("participation", "bench") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let user_id: i64 = arg(&matches, 3, "USER_ID");
+            let file: String = arg(&matches, 4, "FILE");
+            let (_, track) = read_track_file(&file);
+            let options = SimplifyOptions::simplified();
+            let benchmark =
+                match benchmark_matching(&db, Some(event_id), user_id, &track.points, &options) {
+                    Ok(benchmark) => benchmark,
+                    Err(Error::Invalid(report)) => {
+                        for issue in report.issues {
+                            println!("rejected: {} ({} times)", issue.message, issue.count);
+                        }
+                        process::exit(1);
+                    }
+                    Err(err) => fail(&format!("could not match {}: {}", file, err)),
+                };
+            for (label, run) in &[
+                ("raw", &benchmark.raw),
+                ("simplified", &benchmark.simplified),
+            ] {
+                println!(
+                    "{}: {} points matched in {:.3}s, {} attempts",
+                    label,
+                    run.points,
+                    run.seconds,
+                    run.attempts.len()
+                );
+            }
+            match benchmark.max_difference() {
+                Some(difference) => println!("attempt times differ by up to {:.1}s", difference),
+                None => println!("raw and simplified tracks timed different attempts"),
+            }
+            // Keep the results to compare runs, e.g. across simplification settings
+            if let Some(path) = matches.opt_str("output") {
+                fs::write(&path, serde_json::to_string_pretty(&benchmark).unwrap()).unwrap();
+            }
+        }
+        ("results", "show") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            print_results(&db, event_id);
+        }
+        ("results", "export") => {
+            let event_id: i64 = arg(&matches, 2, "EVENT_ID");
+            let format_name = matches.opt_str("format").unwrap_or("csv".to_string());
+            let format = match ResultsFormat::from_extension(&format_name) {
+                Some(format) => format,
+                None => fail(&format!("unknown format {}", format_name)),
+            };
+            let event = match get_event(&db, event_id) {
+                Some(event) => event,
+                None => fail(&format!("no event with id {}", event_id)),
+            };
+            let exported = export_results(&event, format);
+            match matches.opt_str("output") {
+                Some(path) => fs::write(&path, exported).unwrap(),
+                None => print!("{}", exported),
+            }
+        }
+        _ => {
+            print_usage(&program, &opts);
+            process::exit(1);
+        }
+    }
+}
@@ -4,6 +4,7 @@ extern crate futures;
 extern crate postgres;
 extern crate r2d2;
 extern crate r2d2_postgres;
+extern crate serde;
 extern crate serde_json;
 
 extern crate frienduro;
@@ -14,12 +15,29 @@ use std::fmt::Write;
 
 use actix_web::http::Method;
 use actix_web::{
-    get, http, middleware, post, web, App, Error, HttpRequest, HttpResponse, HttpServer,
+    delete, get, http, middleware, post, web, App, Error, HttpRequest, HttpResponse, HttpServer,
+};
+use frienduro::compare::DEFAULT_SECTORS;
+use frienduro::export::{export_results, tracks_to_gpx, ResultsFormat};
+use frienduro::formats::read_track;
+use frienduro::sectors::SectorPosition;
+use frienduro::simplify::SimplifyOptions;
+use frienduro::timing::EventTiming;
+use frienduro::{
+    add_segment_sector, compare_attempts, create_activity, create_participation, create_segment,
+    create_user, delete_segment_sector, get_activity, get_event, get_events, get_matched_tracks,
+    get_segment_details, get_segment_efforts, get_segment_leaderboard, get_segment_profile,
+    get_segment_sectors, get_segments, get_split_series, get_upload, get_upload_data, get_user,
+    get_user_activities, get_users, recompute_event, recompute_participation, recompute_segment,
+    reprocess_participation, reprocess_participations, set_event_timing, set_event_window,
+    EventWindow, UploadFile, DEFAULT_TOLERANCE,
 };
-use frienduro::{create_user, get_event, get_events, get_user, get_users};
 use futures::Future;
 use r2d2::Pool;
 use r2d2_postgres::{PostgresConnectionManager, TlsMode};
+use serde::{Deserialize, Serialize};
+
+const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
 
 fn handler_get_users(
     req: HttpRequest,
@@ -69,13 +87,589 @@ fn handler_get_event(
 ) -> HttpResponse {
     let conn = db.get().unwrap();
 
-    let event = get_event(&conn, id.into_inner()).unwrap();
+    let event = match get_event(&conn, id.into_inner()) {
+        Some(event) => event,
+        None => return HttpResponse::NotFound().finish(),
+    };
 
     HttpResponse::Ok()
         .content_type("application/json")
         .body(serde_json::to_string(&event).unwrap())
 }
 
+#[get("/api/events/{id}/results.{format}")]
+fn handler_get_event_results(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    path: web::Path<(i64, String)>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let (id, format_name) = path.into_inner();
+    let format = match ResultsFormat::from_extension(&format_name) {
+        Some(format) => format,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let event = match get_event(&conn, id) {
+        Some(event) => event,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(export_results(&event, format))
+}
+
+#[get("/api/participations/{id}/matched.gpx")]
+fn handler_get_matched_gpx(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    match get_matched_tracks(&conn, id.into_inner()) {
+        Some(tracks) => HttpResponse::Ok()
+            .content_type("application/gpx+xml")
+            .body(tracks_to_gpx(&tracks)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/api/participations/{id}/segments/{segment_id}/series")]
+fn handler_get_split_series(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    path: web::Path<(i64, i64)>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    match get_split_series(&conn, path.0, path.1) {
+        Some(series) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&series).unwrap()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn handler_get_segments(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let segments = get_segments(&conn).unwrap();
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&segments).unwrap())
+}
+
+#[get("/api/segments/{id}")]
+fn handler_get_segment(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    match get_segment_details(&conn, id.into_inner()) {
+        Some(segment) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&segment).unwrap()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/api/segments/{id}/efforts")]
+fn handler_get_segment_efforts(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let efforts = get_segment_efforts(&conn, id.into_inner());
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&efforts).unwrap())
+}
+
+#[get("/api/segments/{id}/profile")]
+fn handler_get_segment_profile(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    match get_segment_profile(&conn, id.into_inner()) {
+        Some(profile) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&profile).unwrap()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    limit: Option<i64>,
+}
+
+#[get("/api/segments/{id}/leaderboard")]
+fn handler_get_segment_leaderboard(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+    query: web::Query<LeaderboardQuery>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let leaderboard = get_segment_leaderboard(&conn, id.into_inner(), query.limit);
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&leaderboard).unwrap())
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    a: i64,
+    b: i64,
+    sectors: Option<usize>,
+}
+
+#[get("/api/segments/{id}/compare")]
+fn handler_compare_attempts(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+    query: web::Query<CompareQuery>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let sectors = query.sectors.unwrap_or(DEFAULT_SECTORS);
+    match compare_attempts(&conn, id.into_inner(), query.a, query.b, sectors).unwrap() {
+        Some(comparison) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&comparison).unwrap()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    user_id: i64,
+    /// Name of the uploaded file, stored with it
+    filename: Option<String>,
+    /// Drop redundant points before storing the track, off unless asked for
+    simplify: Option<bool>,
+}
+
+fn simplify_options(simplify: Option<bool>) -> SimplifyOptions {
+    if simplify.unwrap_or(false) {
+        SimplifyOptions::simplified()
+    } else {
+        SimplifyOptions::default()
+    }
+}
+
+#[derive(Serialize)]
+struct ParticipationResponse {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct SegmentQuery {
+    name: String,
+    tolerance: Option<f64>,
+    filename: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SegmentResponse {
+    id: i64,
+}
+
+fn handler_create_segment(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    query: web::Query<SegmentQuery>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let track = match read_track(&body) {
+        Ok(track) => track,
+        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+    };
+
+    let upload = UploadFile {
+        data: &body,
+        filename: query.filename.as_ref().map(|name| name.as_str()),
+        uploader_id: None,
+    };
+    let id = create_segment(
+        &conn,
+        &query.name,
+        query.tolerance.unwrap_or(DEFAULT_TOLERANCE),
+        &track.points,
+        Some(&upload),
+    )
+    .unwrap();
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&SegmentResponse { id }).unwrap())
+}
+
+fn handler_create_participation(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+    query: web::Query<UploadQuery>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let track = match read_track(&body) {
+        Ok(track) => track,
+        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+    };
+
+    let upload = UploadFile {
+        data: &body,
+        filename: query.filename.as_ref().map(|name| name.as_str()),
+        uploader_id: Some(query.user_id),
+    };
+    match create_participation(
+        &conn,
+        id.into_inner(),
+        query.user_id,
+        &track.points,
+        &simplify_options(query.simplify),
+        Some(&upload),
+    ) {
+        Ok(id) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&ParticipationResponse { id }).unwrap()),
+        Err(frienduro::Error::Invalid(report)) => HttpResponse::UnprocessableEntity()
+            .content_type("application/json")
+            .body(serde_json::to_string(&report).unwrap()),
+        Err(frienduro::Error::Duplicate(duplicate)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(serde_json::to_string(&duplicate).unwrap()),
+        Err(frienduro::Error::NotFound(what)) => HttpResponse::NotFound().body(what),
+        Err(err) => panic!(err.to_string()),
+    }
+}
+
+fn handler_create_activity(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    query: web::Query<UploadQuery>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let track = match read_track(&body) {
+        Ok(track) => track,
+        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+    };
+
+    let upload = UploadFile {
+        data: &body,
+        filename: query.filename.as_ref().map(|name| name.as_str()),
+        uploader_id: Some(query.user_id),
+    };
+    match create_activity(
+        &conn,
+        query.user_id,
+        &track.points,
+        &simplify_options(query.simplify),
+        Some(&upload),
+    ) {
+        Ok(id) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&ParticipationResponse { id }).unwrap()),
+        Err(frienduro::Error::Invalid(report)) => HttpResponse::UnprocessableEntity()
+            .content_type("application/json")
+            .body(serde_json::to_string(&report).unwrap()),
+        Err(frienduro::Error::Duplicate(duplicate)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(serde_json::to_string(&duplicate).unwrap()),
+        Err(err) => panic!(err.to_string()),
+    }
+}
+
+#[get("/api/activities/{id}")]
+fn handler_get_activity(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    match get_activity(&conn, id.into_inner()) {
+        Some(activity) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&activity).unwrap()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/api/users/{id}/activities")]
+fn handler_get_user_activities(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let activities = get_user_activities(&conn, id.into_inner());
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&activities).unwrap())
+}
+
+#[get("/api/uploads/{id}")]
+fn handler_get_upload(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    match get_upload(&conn, id.into_inner()) {
+        Some(upload) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&upload).unwrap()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/api/uploads/{id}/file")]
+fn handler_get_upload_file(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    match get_upload_data(&conn, id.into_inner()) {
+        Some(data) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(data),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(Serialize)]
+struct RetimeResponse {
+    participations: usize,
+}
+
+#[post("/api/admin/participations/{id}/retime")]
+fn handler_retime_participation(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    if !recompute_participation(&conn, id.into_inner()).unwrap() {
+        return HttpResponse::NotFound().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&RetimeResponse { participations: 1 }).unwrap())
+}
+
+#[post("/api/admin/events/{id}/retime")]
+fn handler_retime_event(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let participations = recompute_event(&conn, id.into_inner()).unwrap();
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&RetimeResponse { participations }).unwrap())
+}
+
+/// Timing settings are posted as JSON, e.g. `{"mode": "elapsed", "mass_start": "...",
+/// "late_penalty": 60}`.
+#[post("/api/admin/events/{id}/timing")]
+fn handler_set_event_timing(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+    timing: web::Json<EventTiming>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let id = id.into_inner();
+    if !set_event_timing(&conn, id, &timing).unwrap() {
+        return HttpResponse::NotFound().body(format!("event {}", id));
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+/// The time window is posted as JSON, e.g. `{"open": "...", "close": "..."}`, a missing end is
+/// left open.
+#[post("/api/admin/events/{id}/window")]
+fn handler_set_event_window(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+    window: web::Json<EventWindow>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let id = id.into_inner();
+    if !set_event_window(&conn, id, &window).unwrap() {
+        return HttpResponse::NotFound().body(format!("event {}", id));
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[post("/api/admin/segments/{id}/retime")]
+fn handler_retime_segment(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let participations = recompute_segment(&conn, id.into_inner()).unwrap();
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&RetimeResponse { participations }).unwrap())
+}
+
+#[get("/api/segments/{id}/sectors")]
+fn handler_get_segment_sectors(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let sectors = get_segment_sectors(&conn, id.into_inner());
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&sectors).unwrap())
+}
+
+/// A split point is placed either `distance` meters from the start or closest to `lat`/`lon`.
+#[derive(Deserialize)]
+struct SectorQuery {
+    distance: Option<f64>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SectorResponse {
+    id: i64,
+}
+
+#[post("/api/admin/segments/{id}/sectors")]
+fn handler_add_segment_sector(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+    query: web::Query<SectorQuery>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let position = match (query.distance, query.lat, query.lon) {
+        (Some(distance), None, None) => SectorPosition::Distance(distance),
+        (None, Some(lat), Some(lon)) => SectorPosition::Point { lat, lon },
+        _ => {
+            return HttpResponse::BadRequest()
+                .body("give either a distance or a lat and lon".to_string())
+        }
+    };
+    let name = query.name.as_ref().map(|name| name.as_str());
+    match add_segment_sector(&conn, id.into_inner(), name, position) {
+        Ok(sector_id) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&SectorResponse { id: sector_id }).unwrap()),
+        Err(frienduro::Error::NotFound(what)) => HttpResponse::NotFound().body(what),
+        Err(err) => panic!(err.to_string()),
+    }
+}
+
+#[delete("/api/admin/sectors/{id}")]
+fn handler_delete_segment_sector(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    if delete_segment_sector(&conn, id.into_inner()).unwrap() {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct ReprocessQuery {
+    simplify: Option<bool>,
+}
+
+#[post("/api/admin/participations/{id}/reprocess")]
+fn handler_reprocess_participation(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    id: web::Path<i64>,
+    query: web::Query<ReprocessQuery>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let options = simplify_options(query.simplify);
+    match reprocess_participation(&conn, id.into_inner(), &options) {
+        Ok(true) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string(&RetimeResponse { participations: 1 }).unwrap()),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(frienduro::Error::Invalid(report)) => HttpResponse::UnprocessableEntity()
+            .content_type("application/json")
+            .body(serde_json::to_string(&report).unwrap()),
+        Err(err) => panic!(err.to_string()),
+    }
+}
+
+#[post("/api/admin/reprocess")]
+fn handler_reprocess_all(
+    req: HttpRequest,
+    db: web::Data<Pool<PostgresConnectionManager>>,
+    query: web::Query<ReprocessQuery>,
+) -> HttpResponse {
+    let conn = db.get().unwrap();
+
+    let options = simplify_options(query.simplify);
+    let participations = reprocess_participations(&conn, None, &options).unwrap();
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&RetimeResponse { participations }).unwrap())
+}
+
 fn main() {
     std::env::set_var("RUST_LOG", "actix_web=info");
     dotenv().ok();
@@ -93,8 +687,46 @@ fn main() {
                     .route(web::post().to(handler_create_user))
                     .route(web::get().to(handler_get_users)),
             )
+            .service(
+                web::resource("/api/events/{id}/participations")
+                    .data(web::PayloadConfig::new(MAX_UPLOAD_SIZE))
+                    .route(web::post().to(handler_create_participation)),
+            )
+            .service(
+                web::resource("/api/activities")
+                    .data(web::PayloadConfig::new(MAX_UPLOAD_SIZE))
+                    .route(web::post().to(handler_create_activity)),
+            )
+            .service(
+                web::resource("/api/segments")
+                    .data(web::PayloadConfig::new(MAX_UPLOAD_SIZE))
+                    .route(web::post().to(handler_create_segment))
+                    .route(web::get().to(handler_get_segments)),
+            )
             .service(handler_get_events)
             .service(handler_get_event)
+            .service(handler_get_event_results)
+            .service(handler_get_matched_gpx)
+            .service(handler_get_split_series)
+            .service(handler_get_segment)
+            .service(handler_get_activity)
+            .service(handler_get_user_activities)
+            .service(handler_get_segment_efforts)
+            .service(handler_get_segment_leaderboard)
+            .service(handler_get_segment_profile)
+            .service(handler_compare_attempts)
+            .service(handler_retime_participation)
+            .service(handler_retime_event)
+            .service(handler_set_event_timing)
+            .service(handler_set_event_window)
+            .service(handler_retime_segment)
+            .service(handler_get_segment_sectors)
+            .service(handler_add_segment_sector)
+            .service(handler_delete_segment_sector)
+            .service(handler_get_upload)
+            .service(handler_get_upload_file)
+            .service(handler_reprocess_participation)
+            .service(handler_reprocess_all)
     })
     .bind("127.0.0.1:8088");
 
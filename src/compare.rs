@@ -0,0 +1,212 @@
+//! Head-to-head comparison of two attempts on the same segment. Both attempts are aligned by
+//! distance along the segment line rather than distance ridden, so a rider cutting a corner or
+//! wandering off the line is still compared at the same spot.
+
+use serde::{Deserialize, Serialize};
+
+use speed::{DistanceTime, SERIES_STEP};
+
+/// Number of equal sectors a segment without split points is divided into unless asked
+/// otherwise.
+pub const DEFAULT_SECTORS: usize = 5;
+
+/// Difference in time between two riders at a distance along the segment. Positive when the
+/// second rider is behind.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Gap {
+    pub distance: f64,
+    pub gap: f64,
+}
+
+/// Time both riders took through a part of the segment.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct SectorDelta {
+    /// Meters from the start of the segment
+    pub start: f64,
+    pub end: f64,
+    pub seconds_a: f64,
+    pub seconds_b: f64,
+    /// Time lost by the second rider in this sector, negative if gained
+    pub delta: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Comparison {
+    pub segment_id: i64,
+    /// Length of the segment in meters
+    pub length: f64,
+    pub participation_a: i64,
+    pub participation_b: i64,
+    pub gaps: Vec<Gap>,
+    pub sectors: Vec<SectorDelta>,
+}
+
+/// Make the progress along the segment never go back, points located behind an earlier point,
+/// e.g. in a hairpin or from GPS noise, are dropped.
+pub fn monotonic(progress: &[DistanceTime]) -> Vec<DistanceTime> {
+    let mut result: Vec<DistanceTime> = Vec::with_capacity(progress.len());
+    for point in progress {
+        if result
+            .last()
+            .map_or(true, |last| point.distance > last.distance)
+        {
+            result.push(*point);
+        }
+    }
+    result
+}
+
+/// Time at which a distance along the segment was reached, interpolated between the points of a
+/// monotonic progress. Distances before the first or after the last point take their time.
+pub fn time_at(progress: &[DistanceTime], distance: f64) -> Option<f64> {
+    let first = progress.first()?;
+    let last = progress.last()?;
+    if distance <= first.distance {
+        return Some(first.seconds);
+    }
+    if distance >= last.distance {
+        return Some(last.seconds);
+    }
+
+    let i = progress.iter().position(|p| p.distance >= distance)?;
+    let (from, to) = (progress[i - 1], progress[i]);
+    let fraction = (distance - from.distance) / (to.distance - from.distance);
+    Some(from.seconds + (to.seconds - from.seconds) * fraction)
+}
+
+/// Sector boundaries dividing a segment into `count` sectors of equal length, from 0 to `length`.
+pub fn equal_sectors(length: f64, count: usize) -> Vec<f64> {
+    let count = count.max(1);
+    (0..count + 1)
+        .map(|i| length * i as f64 / count as f64)
+        .collect()
+}
+
+/// Sector boundaries at the split points of a segment, given in meters from its start, from 0 to
+/// `length`.
+pub fn split_point_sectors(length: f64, split_points: &[f64]) -> Vec<f64> {
+    let mut boundaries = vec![0.0];
+    boundaries.extend(
+        split_points
+            .iter()
+            .cloned()
+            .filter(|&distance| distance > 0.0 && distance < length),
+    );
+    boundaries.push(length);
+    boundaries
+}
+
+/// Compute the gaps every `SERIES_STEP` meters and the deltas in the sectors between consecutive
+/// `boundaries` between two attempts, given as seconds from the start of each attempt at
+/// distances along the segment.
+pub fn compare_progress(
+    length: f64,
+    a: &[DistanceTime],
+    b: &[DistanceTime],
+    boundaries: &[f64],
+) -> (Vec<Gap>, Vec<SectorDelta>) {
+    let a = monotonic(a);
+    let b = monotonic(b);
+    if a.is_empty() || b.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+
+    // Times are counted from the start of the segment, not the first point of the attempt
+    let start_a = time_at(&a, 0.0).unwrap();
+    let start_b = time_at(&b, 0.0).unwrap();
+    let gap_at = |distance: f64| {
+        (time_at(&b, distance).unwrap() - start_b) - (time_at(&a, distance).unwrap() - start_a)
+    };
+
+    let mut gaps = Vec::new();
+    let mut distance = 0.0;
+    while distance < length {
+        gaps.push(Gap {
+            distance,
+            gap: gap_at(distance),
+        });
+        distance += SERIES_STEP;
+    }
+    gaps.push(Gap {
+        distance: length,
+        gap: gap_at(length),
+    });
+
+    let deltas = boundaries
+        .windows(2)
+        .map(|sector| {
+            let (start, end) = (sector[0], sector[1]);
+            let seconds_a = time_at(&a, end).unwrap() - time_at(&a, start).unwrap();
+            let seconds_b = time_at(&b, end).unwrap() - time_at(&b, start).unwrap();
+            SectorDelta {
+                start,
+                end,
+                seconds_a,
+                seconds_b,
+                delta: seconds_b - seconds_a,
+            }
+        })
+        .collect();
+
+    (gaps, deltas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(points: &[(f64, f64)]) -> Vec<DistanceTime> {
+        points
+            .iter()
+            .map(|&(distance, seconds)| DistanceTime { distance, seconds })
+            .collect()
+    }
+
+    #[test]
+    fn progress_going_back_is_dropped() {
+        let result = monotonic(&progress(&[
+            (0.0, 0.0),
+            (10.0, 1.0),
+            (8.0, 2.0),
+            (20.0, 3.0),
+        ]));
+        let distances = result.iter().map(|p| p.distance).collect::<Vec<f64>>();
+        assert_eq!(distances, vec![0.0, 10.0, 20.0]);
+    }
+
+    #[test]
+    fn time_is_interpolated_and_clamped() {
+        let points = progress(&[(10.0, 5.0), (20.0, 15.0)]);
+        assert_eq!(time_at(&points, 0.0), Some(5.0));
+        assert_eq!(time_at(&points, 15.0), Some(10.0));
+        assert_eq!(time_at(&points, 30.0), Some(15.0));
+        assert_eq!(time_at(&[], 15.0), None);
+    }
+
+    #[test]
+    fn sector_boundaries() {
+        assert_eq!(equal_sectors(100.0, 4), vec![0.0, 25.0, 50.0, 75.0, 100.0]);
+        assert_eq!(equal_sectors(100.0, 0), vec![0.0, 100.0]);
+        assert_eq!(
+            split_point_sectors(100.0, &[0.0, 30.0, 60.0, 100.0]),
+            vec![0.0, 30.0, 60.0, 100.0]
+        );
+    }
+
+    #[test]
+    fn second_rider_losing_time_in_the_second_half() {
+        let a = progress(&[(0.0, 100.0), (50.0, 110.0), (100.0, 120.0)]);
+        let b = progress(&[(0.0, 500.0), (50.0, 510.0), (100.0, 530.0)]);
+
+        let (gaps, sectors) = compare_progress(100.0, &a, &b, &[0.0, 50.0, 100.0]);
+        assert_eq!(gaps.first().unwrap().gap, 0.0);
+        assert_eq!(gaps.last().unwrap().distance, 100.0);
+        assert_eq!(gaps.last().unwrap().gap, 10.0);
+
+        assert_eq!(sectors.len(), 2);
+        assert_eq!(sectors[0].delta, 0.0);
+        assert_eq!(sectors[1].seconds_a, 10.0);
+        assert_eq!(sectors[1].seconds_b, 20.0);
+        assert_eq!(sectors[1].delta, 10.0);
+    }
+}
@@ -0,0 +1,106 @@
+//! Duplicate uploads: the same ride uploaded twice, or a rider entering an event more than once.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Start times of two tracks closer than this are taken to be the same recording.
+pub const SAME_START_SECONDS: f64 = 5.0;
+
+/// Which participation counts when a rider enters an event more than once. Earlier
+/// participations are kept but left out of the results.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Refuse any further participation
+    Reject,
+    /// The latest participation counts
+    Replace,
+    /// The fastest complete participation counts
+    KeepBest,
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> DuplicatePolicy {
+        DuplicatePolicy::Replace
+    }
+}
+
+impl DuplicatePolicy {
+    pub fn from_name(name: &str) -> Option<DuplicatePolicy> {
+        match name {
+            "reject" => Some(DuplicatePolicy::Reject),
+            "replace" => Some(DuplicatePolicy::Replace),
+            "keep-best" => Some(DuplicatePolicy::KeepBest),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DuplicatePolicy::Reject => "reject",
+            DuplicatePolicy::Replace => "replace",
+            DuplicatePolicy::KeepBest => "keep-best",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    /// The same file was uploaded before
+    SameFile,
+    /// A track starting at the same time was uploaded before by the same rider
+    SameStart,
+    /// The rider already entered the event and the event rejects further participations
+    AlreadyEntered,
+}
+
+/// An upload refused as a duplicate of an existing participation.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Duplicate {
+    pub participation_id: i64,
+    pub reason: DuplicateReason,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason {
+            DuplicateReason::SameFile => write!(
+                f,
+                "the same file was already uploaded as participation {}",
+                self.participation_id
+            ),
+            DuplicateReason::SameStart => write!(
+                f,
+                "a track with the same start time was already uploaded as participation {}",
+                self.participation_id
+            ),
+            DuplicateReason::AlreadyEntered => write!(
+                f,
+                "the rider already entered the event with participation {}",
+                self.participation_id
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_participation_counts_by_default() {
+        assert_eq!(DuplicatePolicy::default(), DuplicatePolicy::Replace);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for policy in &[
+            DuplicatePolicy::Reject,
+            DuplicatePolicy::Replace,
+            DuplicatePolicy::KeepBest,
+        ] {
+            assert_eq!(DuplicatePolicy::from_name(policy.name()), Some(*policy));
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Elevation profiles and climbing statistics of segments.
+
+use serde::{Deserialize, Serialize};
+
+use track::TrackPoint;
+
+/// Changes in elevation smaller than this many meters are taken as noise when summing ascent and
+/// descent, GPS and barometer readings wander by a meter or two even on flat ground.
+pub const ELEVATION_THRESHOLD: f64 = 2.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ProfilePoint {
+    /// Meters from the start of the line
+    pub distance: f64,
+    /// Meters above sea level
+    pub elevation: f64,
+}
+
+/// Climbing statistics of a line, all in meters except the gradient.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct ClimbStats {
+    pub ascent: f64,
+    pub descent: f64,
+    pub min_elevation: f64,
+    pub max_elevation: f64,
+    /// Elevation difference between the end and the start in percent of the length
+    pub avg_gradient: f64,
+}
+
+/// Elevation against distance along a line, skipping points without elevation.
+pub fn elevation_profile(track: &[TrackPoint]) -> Vec<ProfilePoint> {
+    let mut profile = Vec::new();
+    let mut distance = 0.0;
+    for (i, point) in track.iter().enumerate() {
+        if i > 0 {
+            distance += track[i - 1].distance(point);
+        }
+        if let Some(elevation) = point.elevation {
+            profile.push(ProfilePoint {
+                distance,
+                elevation,
+            });
+        }
+    }
+    profile
+}
+
+/// Compute the climbing statistics of a profile. Returns None if it has no elevation at all.
+pub fn climb_stats(profile: &[ProfilePoint]) -> Option<ClimbStats> {
+    let first = profile.first()?;
+    let last = profile.last()?;
+
+    let mut stats = ClimbStats {
+        min_elevation: first.elevation,
+        max_elevation: first.elevation,
+        ..ClimbStats::default()
+    };
+
+    // Only count a change once it exceeds the threshold from the last counted elevation
+    let mut reference = first.elevation;
+    for point in profile {
+        stats.min_elevation = stats.min_elevation.min(point.elevation);
+        stats.max_elevation = stats.max_elevation.max(point.elevation);
+
+        let change = point.elevation - reference;
+        if change >= ELEVATION_THRESHOLD {
+            stats.ascent += change;
+            reference = point.elevation;
+        } else if change <= -ELEVATION_THRESHOLD {
+            stats.descent -= change;
+            reference = point.elevation;
+        }
+    }
+
+    if last.distance > 0.0 {
+        stats.avg_gradient = (last.elevation - first.elevation) / last.distance * 100.0;
+    }
+
+    Some(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, elevation: Option<f64>) -> TrackPoint {
+        TrackPoint {
+            lat,
+            lon: 17.0,
+            elevation,
+            time: None,
+            heart_rate: None,
+            cadence: None,
+            temperature: None,
+        }
+    }
+
+    fn profile(elevations: &[f64]) -> Vec<ProfilePoint> {
+        elevations
+            .iter()
+            .enumerate()
+            .map(|(i, &elevation)| ProfilePoint {
+                distance: i as f64 * 100.0,
+                elevation,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn points_without_elevation_are_skipped() {
+        let track = [
+            point(62.0, Some(10.0)),
+            point(62.001, None),
+            point(62.002, Some(20.0)),
+        ];
+        let result = elevation_profile(&track);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].distance, 0.0);
+        assert!((result[1].distance - track[0].distance(&track[2])).abs() < 0.01);
+    }
+
+    #[test]
+    fn noise_below_the_threshold_is_ignored() {
+        let stats = climb_stats(&profile(&[100.0, 101.0, 100.0, 101.0, 110.0, 105.0])).unwrap();
+        assert_eq!(stats.ascent, 10.0);
+        assert_eq!(stats.descent, 5.0);
+        assert_eq!(stats.min_elevation, 100.0);
+        assert_eq!(stats.max_elevation, 110.0);
+        assert!((stats.avg_gradient - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn no_elevation_has_no_stats() {
+        assert!(climb_stats(&[]).is_none());
+    }
+}
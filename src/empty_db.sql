@@ -1,8 +1,14 @@
+DROP TABLE participation_sensors;
+DROP TABLE event_riders;
+DROP TABLE event_categories;
 DROP TABLE participation_segments;
 DROP TABLE participations;
 DROP TABLE event_segments;
 DROP TABLE events;
+DROP TABLE segment_sectors;
 DROP TABLE segments;
+DROP TABLE uploads;
 DROP TABLE users;
+DROP TABLE schema_migrations;
 
 DROP EXTENSION postgis;
@@ -0,0 +1,49 @@
+use std::fmt;
+
+use duplicate::Duplicate;
+use validation::ValidationReport;
+
+#[derive(Debug)]
+pub enum Error {
+    Database(postgres::Error),
+    Invalid(ValidationReport),
+    Duplicate(Duplicate),
+    /// An event, segment or other record the request refers to does not exist
+    NotFound(String),
+    /// The uploaded file could not be read
+    Parse(String),
+    Io(std::io::Error),
+}
+
+impl From<postgres::Error> for Error {
+    fn from(err: postgres::Error) -> Error {
+        Error::Database(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Database(err) => write!(f, "database error: {}", err),
+            Error::Parse(err) => write!(f, "parse error: {}", err),
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Duplicate(duplicate) => write!(f, "duplicate upload: {}", duplicate),
+            Error::NotFound(what) => write!(f, "{} not found", what),
+            Error::Invalid(report) => {
+                write!(f, "invalid track:")?;
+                for issue in &report.issues {
+                    write!(f, " {} ({} times);", issue.message, issue.count)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,291 @@
+//! Exporters for event results, as CSV for spreadsheets, JSON for other tools and a
+//! self-contained HTML page for printing, and for tracks as GPX.
+
+use chrono::prelude::*;
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+
+use sensors::SensorSummary;
+use speed::SpeedStats;
+use track::NamedTrack;
+use EventDetails;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultsFormat {
+    Csv,
+    Json,
+    Html,
+}
+
+impl ResultsFormat {
+    pub fn from_extension(extension: &str) -> Option<ResultsFormat> {
+        match extension {
+            "csv" => Some(ResultsFormat::Csv),
+            "json" => Some(ResultsFormat::Json),
+            "html" => Some(ResultsFormat::Html),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ResultsFormat::Csv => "text/csv; charset=utf-8",
+            ResultsFormat::Json => "application/json",
+            ResultsFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// Version of the JSON results schema, bump it on any incompatible change.
+pub const RESULTS_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResultsStage {
+    pub segment_id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResultsSplit {
+    pub segment_id: i64,
+    pub elapsed_seconds: Option<f64>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub sensors: SensorSummary,
+    pub speed: Option<SpeedStats>,
+    pub sectors: Option<Vec<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResultsRow {
+    /// Position in the results, unset for riders that did not finish
+    pub rank: Option<usize>,
+    pub participation_id: i64,
+    pub rider: String,
+    pub total_seconds: Option<f64>,
+    /// Included in the total, for events timed with liaison cutoffs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub penalty_seconds: Option<f64>,
+    pub splits: Vec<ResultsSplit>,
+}
+
+/// The stable JSON representation of the results of an event.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResultsDocument {
+    pub schema_version: u32,
+    pub event_id: i64,
+    pub event_name: String,
+    pub date: Option<NaiveDate>,
+    pub stages: Vec<ResultsStage>,
+    pub results: Vec<ResultsRow>,
+}
+
+impl<'a> From<&'a EventDetails> for ResultsDocument {
+    fn from(event: &'a EventDetails) -> ResultsDocument {
+        let mut rank = 0;
+        let results = event
+            .results
+            .iter()
+            .map(|result| {
+                let finished = result.time != 0.0;
+                if finished {
+                    rank += 1;
+                }
+
+                ResultsRow {
+                    rank: if finished { Some(rank) } else { None },
+                    participation_id: result.participation_id,
+                    rider: result.username.clone(),
+                    total_seconds: if finished { Some(result.time) } else { None },
+                    penalty_seconds: result.penalty.filter(|&p| p > 0.0),
+                    splits: result
+                        .stages
+                        .iter()
+                        .map(|stage| ResultsSplit {
+                            segment_id: stage.segment_id,
+                            elapsed_seconds: stage.elapsed,
+                            started_at: stage.started_at,
+                            finished_at: stage.finished_at,
+                            sensors: stage.sensors,
+                            speed: stage.speed,
+                            sectors: stage.sectors.clone(),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        ResultsDocument {
+            schema_version: RESULTS_SCHEMA_VERSION,
+            event_id: event.id,
+            event_name: event.name.clone(),
+            date: event.date,
+            stages: event
+                .stages
+                .iter()
+                .map(|stage| ResultsStage {
+                    segment_id: stage.segment_id,
+                    name: stage.name.clone(),
+                })
+                .collect(),
+            results,
+        }
+    }
+}
+
+/// Format a duration as h:mm:ss.s, or just m:ss.s when below an hour.
+pub fn format_duration(seconds: f64) -> String {
+    let tenths = (seconds * 10.0).round() as i64;
+    let hours = tenths / 36000;
+    let minutes = (tenths / 600) % 60;
+    let seconds = (tenths % 600) as f64 / 10.0;
+    if hours > 0 {
+        format!("{}:{:02}:{:04.1}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:04.1}", minutes, seconds)
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn optional_duration(seconds: Option<f64>) -> String {
+    seconds.map(format_duration).unwrap_or_default()
+}
+
+/// Export results as CSV, one row per rider with total and stage times in seconds.
+pub fn results_to_csv(event: &EventDetails) -> String {
+    let document = ResultsDocument::from(event);
+
+    let mut header = vec!["rank".to_string(), "rider".to_string(), "total".to_string()];
+    header.extend(document.stages.iter().map(|stage| csv_field(&stage.name)));
+
+    let mut lines = vec![header.join(",")];
+    for row in &document.results {
+        let mut fields = vec![
+            row.rank.map(|r| r.to_string()).unwrap_or("DNF".to_string()),
+            csv_field(&row.rider),
+            row.total_seconds.map(|t| t.to_string()).unwrap_or_default(),
+        ];
+        fields.extend(row.splits.iter().map(|split| {
+            split
+                .elapsed_seconds
+                .map(|t| t.to_string())
+                .unwrap_or_default()
+        }));
+        lines.push(fields.join(","));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Export results in the stable JSON schema described by `ResultsDocument`.
+pub fn results_to_json(event: &EventDetails) -> String {
+    serde_json::to_string_pretty(&ResultsDocument::from(event)).unwrap()
+}
+
+/// Export results as a standalone HTML page, styled for printing.
+pub fn results_to_html(event: &EventDetails) -> String {
+    let document = ResultsDocument::from(event);
+    let title = html_escape(&document.event_name);
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{} results</title>\n", title));
+    html.push_str(
+        "<style>\
+         body { font-family: sans-serif; }\
+         table { border-collapse: collapse; }\
+         th, td { border: 1px solid #999; padding: 0.2em 0.6em; text-align: right; }\
+         td.rider { text-align: left; }\
+         @media print { body { font-size: 10pt; } }\
+         </style>\n",
+    );
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>{}</h1>\n", title));
+    if let Some(date) = document.date {
+        html.push_str(&format!("<p>{}</p>\n", date));
+    }
+
+    html.push_str("<table>\n<tr><th>#</th><th>Rider</th><th>Total</th>");
+    for stage in &document.stages {
+        html.push_str(&format!("<th>{}</th>", html_escape(&stage.name)));
+    }
+    html.push_str("</tr>\n");
+
+    for row in &document.results {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td class=\"rider\">{}</td><td>{}</td>",
+            row.rank.map(|r| r.to_string()).unwrap_or("DNF".to_string()),
+            html_escape(&row.rider),
+            optional_duration(row.total_seconds)
+        ));
+        for split in &row.splits {
+            html.push_str(&format!(
+                "<td>{}</td>",
+                optional_duration(split.elapsed_seconds)
+            ));
+        }
+        html.push_str("</tr>\n");
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+pub fn export_results(event: &EventDetails, format: ResultsFormat) -> String {
+    match format {
+        ResultsFormat::Csv => results_to_csv(event),
+        ResultsFormat::Json => results_to_json(event),
+        ResultsFormat::Html => results_to_html(event),
+    }
+}
+
+/// Write tracks to a GPX document, one `<trk>` per track.
+pub fn tracks_to_gpx(tracks: &[NamedTrack]) -> String {
+    let mut gpx = String::new();
+    gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    gpx.push_str(
+        "<gpx version=\"1.1\" creator=\"frienduro\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+
+    for track in tracks {
+        gpx.push_str(" <trk>\n");
+        gpx.push_str(&format!("  <name>{}</name>\n", html_escape(&track.name)));
+        gpx.push_str("  <trkseg>\n");
+        for point in &track.points {
+            gpx.push_str(&format!(
+                "   <trkpt lat=\"{:.7}\" lon=\"{:.7}\">",
+                point.lat, point.lon
+            ));
+            if let Some(elevation) = point.elevation {
+                gpx.push_str(&format!("<ele>{:.1}</ele>", elevation));
+            }
+            if let Some(time) = point.time {
+                gpx.push_str(&format!(
+                    "<time>{}</time>",
+                    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+                ));
+            }
+            gpx.push_str("</trkpt>\n");
+        }
+        gpx.push_str("  </trkseg>\n");
+        gpx.push_str(" </trk>\n");
+    }
+
+    gpx.push_str("</gpx>\n");
+    gpx
+}
@@ -0,0 +1,273 @@
+//! Decoder for the parts of the Garmin FIT format we need: the `record` messages holding the
+//! recorded positions of an activity.
+
+use chrono::prelude::*;
+
+use track::TrackPoint;
+
+const MESG_RECORD: u16 = 20;
+
+const FIELD_POSITION_LAT: u8 = 0;
+const FIELD_POSITION_LONG: u8 = 1;
+const FIELD_ALTITUDE: u8 = 2;
+const FIELD_HEART_RATE: u8 = 3;
+const FIELD_CADENCE: u8 = 4;
+const FIELD_TEMPERATURE: u8 = 13;
+const FIELD_ENHANCED_ALTITUDE: u8 = 78;
+const FIELD_TIMESTAMP: u8 = 253;
+
+/// Seconds between the unix epoch and the FIT epoch, 1989-12-31T00:00:00Z.
+const FIT_EPOCH: i64 = 631_065_600;
+
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401,
+    0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, byte| {
+        let mut tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[(byte & 0xF) as usize];
+        tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc ^ tmp ^ CRC_TABLE[((byte >> 4) & 0xF) as usize]
+    })
+}
+
+/// Check if the data starts with a FIT file header.
+pub fn is_fit(data: &[u8]) -> bool {
+    data.len() >= 12 && &data[8..12] == b".FIT"
+}
+
+struct FieldDefinition {
+    number: u8,
+    size: usize,
+}
+
+struct MessageDefinition {
+    big_endian: bool,
+    global_number: u16,
+    fields: Vec<FieldDefinition>,
+    /// Total size of the developer fields, which we skip
+    developer_size: usize,
+}
+
+fn read_uint(bytes: &[u8], big_endian: bool) -> u64 {
+    let mut value: u64 = 0;
+    for i in 0..bytes.len() {
+        let byte = if big_endian {
+            bytes[i]
+        } else {
+            bytes[bytes.len() - 1 - i]
+        };
+        value = (value << 8) | byte as u64;
+    }
+    value
+}
+
+#[derive(Default)]
+struct Record {
+    timestamp: Option<u32>,
+    lat: Option<i32>,
+    lon: Option<i32>,
+    altitude: Option<u32>,
+    heart_rate: Option<u8>,
+    cadence: Option<u8>,
+    temperature: Option<i8>,
+}
+
+impl Record {
+    fn read_field(&mut self, number: u8, bytes: &[u8], big_endian: bool) {
+        let value = read_uint(bytes, big_endian);
+        match (number, bytes.len()) {
+            (FIELD_TIMESTAMP, 4) if value != 0xFFFF_FFFF => self.timestamp = Some(value as u32),
+            (FIELD_POSITION_LAT, 4) if value != 0x7FFF_FFFF => self.lat = Some(value as u32 as i32),
+            (FIELD_POSITION_LONG, 4) if value != 0x7FFF_FFFF => {
+                self.lon = Some(value as u32 as i32)
+            }
+            (FIELD_ALTITUDE, 2) if value != 0xFFFF => self.altitude = Some(value as u32),
+            (FIELD_ENHANCED_ALTITUDE, 4) if value != 0xFFFF_FFFF => {
+                self.altitude = Some(value as u32)
+            }
+            (FIELD_HEART_RATE, 1) if value != 0xFF => self.heart_rate = Some(value as u8),
+            (FIELD_CADENCE, 1) if value != 0xFF => self.cadence = Some(value as u8),
+            (FIELD_TEMPERATURE, 1) if value != 0x7F => self.temperature = Some(value as u8 as i8),
+            _ => (),
+        }
+    }
+}
+
+fn semicircles_to_degrees(value: i32) -> f64 {
+    value as f64 * (180.0 / 2_147_483_648.0)
+}
+
+/// Decode all records with a position from a FIT file, in file order.
+pub fn read_fit(data: &[u8]) -> Result<Vec<TrackPoint>, String> {
+    if !is_fit(data) {
+        return Err("not a FIT file".to_string());
+    }
+
+    let header_size = data[0] as usize;
+    let data_size = read_uint(&data[4..8], false) as usize;
+    let end = header_size + data_size;
+    if header_size < 12 || data.len() < end + 2 {
+        return Err("truncated FIT file".to_string());
+    }
+    if read_uint(&data[end..end + 2], false) as u16 != crc16(&data[..end]) {
+        return Err("FIT file CRC mismatch".to_string());
+    }
+
+    let mut definitions: Vec<Option<MessageDefinition>> = (0..16).map(|_| None).collect();
+    let mut last_timestamp: u32 = 0;
+    let mut points: Vec<TrackPoint> = Vec::new();
+
+    let mut pos = header_size;
+    while pos < end {
+        let header = data[pos];
+        pos += 1;
+
+        // Compressed timestamp header, a data message with a 5 bit time offset
+        let (local_type, time_offset) = if header & 0x80 != 0 {
+            ((header >> 5) & 0x3, Some(header & 0x1F))
+        } else {
+            (header & 0xF, None)
+        };
+
+        if time_offset.is_none() && header & 0x40 != 0 {
+            if pos + 5 > end {
+                return Err("truncated FIT definition message".to_string());
+            }
+            let big_endian = data[pos + 1] == 1;
+            let global_number = read_uint(&data[pos + 2..pos + 4], big_endian) as u16;
+            let field_count = data[pos + 4] as usize;
+            pos += 5;
+
+            if pos + field_count * 3 > end {
+                return Err("truncated FIT definition message".to_string());
+            }
+            let fields = (0..field_count)
+                .map(|i| FieldDefinition {
+                    number: data[pos + i * 3],
+                    size: data[pos + i * 3 + 1] as usize,
+                })
+                .collect();
+            pos += field_count * 3;
+
+            let mut developer_size = 0;
+            if header & 0x20 != 0 {
+                if pos >= end {
+                    return Err("truncated FIT definition message".to_string());
+                }
+                let developer_count = data[pos] as usize;
+                pos += 1;
+                if pos + developer_count * 3 > end {
+                    return Err("truncated FIT definition message".to_string());
+                }
+                developer_size = (0..developer_count)
+                    .map(|i| data[pos + i * 3 + 1] as usize)
+                    .sum();
+                pos += developer_count * 3;
+            }
+
+            definitions[local_type as usize] = Some(MessageDefinition {
+                big_endian,
+                global_number,
+                fields,
+                developer_size,
+            });
+            continue;
+        }
+
+        let definition = match definitions[local_type as usize] {
+            Some(ref definition) => definition,
+            None => return Err("FIT data message without definition".to_string()),
+        };
+
+        let mut record = Record::default();
+        for field in &definition.fields {
+            if pos + field.size > end {
+                return Err("truncated FIT data message".to_string());
+            }
+            record.read_field(
+                field.number,
+                &data[pos..pos + field.size],
+                definition.big_endian,
+            );
+            pos += field.size;
+        }
+        pos += definition.developer_size;
+
+        if let Some(offset) = time_offset {
+            let mut timestamp = (last_timestamp & !0x1F) | offset as u32;
+            if (offset as u32) < (last_timestamp & 0x1F) {
+                timestamp += 0x20;
+            }
+            record.timestamp = Some(timestamp);
+        }
+        if let Some(timestamp) = record.timestamp {
+            last_timestamp = timestamp;
+        }
+
+        if definition.global_number != MESG_RECORD {
+            continue;
+        }
+
+        if let (Some(lat), Some(lon)) = (record.lat, record.lon) {
+            points.push(TrackPoint {
+                lat: semicircles_to_degrees(lat),
+                lon: semicircles_to_degrees(lon),
+                elevation: record.altitude.map(|a| a as f64 / 5.0 - 500.0),
+                time: record
+                    .timestamp
+                    .map(|t| Utc.timestamp(FIT_EPOCH + t as i64, 0)),
+                heart_rate: record.heart_rate,
+                cadence: record.cadence,
+                temperature: record.temperature.map(|t| t as f64),
+            });
+        }
+    }
+
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use formats::read_track;
+    use std::fs;
+
+    fn fixture(path: &str) -> Vec<u8> {
+        fs::read(format!("{}/test_data/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap()
+    }
+
+    #[test]
+    fn fixtures_hold_the_same_rides_as_gpx() {
+        for name in &["Jane Doe", "John Doe"] {
+            let points =
+                read_fit(&fixture(&format!("Test Race 1 FIT/users/{}.fit", name))).unwrap();
+            let gpx = read_track(&fixture(&format!("Test Race 1/users/{}.gpx", name))).unwrap();
+
+            assert_eq!(points.len(), gpx.points.len());
+            for (point, expected) in points.iter().zip(&gpx.points) {
+                assert_eq!(point.time, expected.time);
+                assert!(point.distance(expected) < 1.0);
+                assert!(point.heart_rate.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_file_is_rejected() {
+        let mut data = fixture("Test Race 1 FIT/users/John Doe.fit");
+        let middle = data.len() / 2;
+        data[middle] ^= 0xFF;
+        assert_eq!(read_fit(&data).unwrap_err(), "FIT file CRC mismatch");
+    }
+
+    #[test]
+    fn other_files_are_not_fit() {
+        assert!(!is_fit(b"<gpx></gpx>"));
+        assert!(read_fit(b"<gpx></gpx>").is_err());
+    }
+}
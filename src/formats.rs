@@ -0,0 +1,82 @@
+use error::Error;
+use fit::{is_fit, read_fit};
+use geojson::{is_geojson, read_geojson};
+use gpx_extensions::read_gpx_extensions;
+use kml::{is_kml, read_kml};
+use tcx::{is_tcx, read_tcx};
+use track::{merge_gpx_tracks, merge_segments, MergedTrack};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Gpx,
+    Fit,
+    Tcx,
+    GeoJson,
+    Kml,
+}
+
+impl FileFormat {
+    /// The usual extension of files in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Gpx => "gpx",
+            FileFormat::Fit => "fit",
+            FileFormat::Tcx => "tcx",
+            FileFormat::GeoJson => "geojson",
+            FileFormat::Kml => "kml",
+        }
+    }
+}
+
+/// Guess the format of a track file from its content, file names are not trusted.
+pub fn detect_format(data: &[u8]) -> Option<FileFormat> {
+    if is_fit(data) {
+        return Some(FileFormat::Fit);
+    }
+
+    // Look at the start of the document, skipping any XML declaration and comments
+    let head = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+    if is_tcx(&head) {
+        Some(FileFormat::Tcx)
+    } else if head.contains("<gpx") {
+        Some(FileFormat::Gpx)
+    } else if is_kml(&head) {
+        Some(FileFormat::Kml)
+    } else if is_geojson(&head) {
+        Some(FileFormat::GeoJson)
+    } else {
+        None
+    }
+}
+
+/// Read a track file of any supported format into a single merged track.
+///
+/// GeoJSON and KML usually describe untimed lines, they can be used for segments but will not
+/// pass validation as participations.
+pub fn read_track(data: &[u8]) -> Result<MergedTrack, Error> {
+    match detect_format(data) {
+        Some(FileFormat::Gpx) => {
+            let gpx = gpx::read(std::io::Cursor::new(data))
+                .map_err(|err| Error::Parse(err.to_string()))?;
+            let extensions = read_gpx_extensions(data).map_err(Error::Parse)?;
+            Ok(merge_gpx_tracks(&gpx, &extensions))
+        }
+        Some(FileFormat::Fit) => {
+            let points = read_fit(data).map_err(Error::Parse)?;
+            Ok(merge_segments(vec![points]))
+        }
+        Some(FileFormat::Tcx) => {
+            let tracks = read_tcx(data).map_err(Error::Parse)?;
+            Ok(merge_segments(tracks))
+        }
+        Some(FileFormat::GeoJson) => {
+            let lines = read_geojson(data).map_err(Error::Parse)?;
+            Ok(merge_segments(lines))
+        }
+        Some(FileFormat::Kml) => {
+            let lines = read_kml(data).map_err(Error::Parse)?;
+            Ok(merge_segments(lines))
+        }
+        None => Err(Error::Parse("unknown file format".to_string())),
+    }
+}
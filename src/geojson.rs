@@ -0,0 +1,104 @@
+//! Reader for GeoJSON line geometries, as exported by most mapping tools.
+
+use serde_json::Value;
+
+use track::TrackPoint;
+
+/// Check if the data looks like a GeoJSON document.
+pub fn is_geojson(head: &str) -> bool {
+    head.trim_start().starts_with('{') && head.contains("\"type\"")
+}
+
+fn read_position(position: &Value) -> Option<TrackPoint> {
+    let coords = position.as_array()?;
+    Some(TrackPoint {
+        lon: coords.get(0)?.as_f64()?,
+        lat: coords.get(1)?.as_f64()?,
+        elevation: coords.get(2).and_then(|e| e.as_f64()),
+        time: None,
+        heart_rate: None,
+        cadence: None,
+        temperature: None,
+    })
+}
+
+fn read_line(coordinates: &Value) -> Result<Vec<TrackPoint>, String> {
+    coordinates
+        .as_array()
+        .ok_or("LineString without coordinates".to_string())?
+        .iter()
+        .map(|position| read_position(position).ok_or("invalid position".to_string()))
+        .collect()
+}
+
+fn read_object(object: &Value, lines: &mut Vec<Vec<TrackPoint>>) -> Result<(), String> {
+    match object["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in object["features"].as_array().unwrap_or(&Vec::new()) {
+                read_object(feature, lines)?;
+            }
+        }
+        Some("Feature") => read_object(&object["geometry"], lines)?,
+        Some("GeometryCollection") => {
+            for geometry in object["geometries"].as_array().unwrap_or(&Vec::new()) {
+                read_object(geometry, lines)?;
+            }
+        }
+        Some("LineString") => lines.push(read_line(&object["coordinates"])?),
+        Some("MultiLineString") => {
+            for line in object["coordinates"].as_array().unwrap_or(&Vec::new()) {
+                lines.push(read_line(line)?);
+            }
+        }
+        // Points and polygons can't describe a segment, ignore them
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Read all LineString and MultiLineString geometries of a GeoJSON document, in document order.
+pub fn read_geojson(data: &[u8]) -> Result<Vec<Vec<TrackPoint>>, String> {
+    let root: Value = serde_json::from_slice(data).map_err(|err| err.to_string())?;
+
+    let mut lines = Vec::new();
+    read_object(&root, &mut lines)?;
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_read_from_features() {
+        let data = br#"{
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [17.0, 62.0]}},
+                {"type": "Feature", "geometry": {
+                    "type": "LineString",
+                    "coordinates": [[17.9, 62.5, 10.0], [17.91, 62.51]]
+                }},
+                {"type": "Feature", "geometry": {
+                    "type": "MultiLineString",
+                    "coordinates": [[[17.92, 62.52], [17.93, 62.53]], [[17.94, 62.54], [17.95, 62.55]]]
+                }}
+            ]
+        }"#;
+
+        assert!(is_geojson(&String::from_utf8_lossy(data)));
+        let lines = read_geojson(data).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!((lines[0][0].lat, lines[0][0].lon), (62.5, 17.9));
+        assert_eq!(lines[0][0].elevation, Some(10.0));
+        assert_eq!(lines[0][1].elevation, None);
+        assert_eq!((lines[2][1].lat, lines[2][1].lon), (62.55, 17.95));
+    }
+
+    #[test]
+    fn invalid_position_is_an_error() {
+        let data = br#"{"type": "LineString", "coordinates": [[17.9]]}"#;
+        assert!(read_geojson(data).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! Sensor readings stored in the extensions of GPX track points, which the gpx crate skips. Garmin
+//! devices write them as `<gpxtpx:TrackPointExtension>` with `hr`, `cad` and `atemp`, other
+//! applications use similar elements without the namespace or with longer names.
+
+use xml::reader::{EventReader, XmlEvent};
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PointExtensions {
+    pub heart_rate: Option<u8>,
+    pub cadence: Option<u8>,
+    pub temperature: Option<f64>,
+}
+
+/// Read the extensions of every `<trkpt>`, grouped by `<trkseg>` in document order so they line
+/// up with the points read by the gpx crate.
+pub fn read_gpx_extensions(data: &[u8]) -> Result<Vec<Vec<PointExtensions>>, String> {
+    let parser = EventReader::new(std::io::Cursor::new(data));
+
+    let mut path: Vec<String> = Vec::new();
+    let mut segments: Vec<Vec<PointExtensions>> = Vec::new();
+
+    for event in parser {
+        match event.map_err(|err| err.to_string())? {
+            XmlEvent::StartElement { name, .. } => {
+                match name.local_name.as_str() {
+                    "trkseg" => segments.push(Vec::new()),
+                    "trkpt" => {
+                        if let Some(segment) = segments.last_mut() {
+                            segment.push(PointExtensions::default());
+                        }
+                    }
+                    _ => (),
+                }
+                path.push(name.local_name);
+            }
+            XmlEvent::EndElement { .. } => {
+                path.pop();
+            }
+            XmlEvent::Characters(text) => {
+                if !path.iter().any(|element| element == "extensions") {
+                    continue;
+                }
+                let p = match segments.last_mut().and_then(|s| s.last_mut()) {
+                    Some(p) => p,
+                    None => continue,
+                };
+
+                let text = text.trim();
+                match path.last().map(|s| s.as_str()) {
+                    Some("hr") | Some("heartrate") => p.heart_rate = text.parse().ok(),
+                    Some("cad") | Some("cadence") => p.cadence = text.parse().ok(),
+                    Some("atemp") | Some("temp") => p.temperature = text.parse().ok(),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(segments)
+}
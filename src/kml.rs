@@ -0,0 +1,142 @@
+//! Reader for KML line geometries, both plain `<LineString>` and timed `<gx:Track>` elements.
+
+use chrono::prelude::*;
+use xml::reader::{EventReader, XmlEvent};
+
+use track::TrackPoint;
+
+/// Check if the data looks like a KML document.
+pub fn is_kml(head: &str) -> bool {
+    head.contains("<kml")
+}
+
+fn read_coordinates(text: &str) -> Result<Vec<TrackPoint>, String> {
+    text.split_whitespace()
+        .map(|tuple| {
+            let values = tuple
+                .split(',')
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|err| format!("invalid coordinate {}: {}", tuple, err))?;
+            if values.len() < 2 {
+                return Err(format!("invalid coordinate {}", tuple));
+            }
+
+            Ok(TrackPoint {
+                lon: values[0],
+                lat: values[1],
+                elevation: values.get(2).cloned(),
+                time: None,
+                heart_rate: None,
+                cadence: None,
+                temperature: None,
+            })
+        })
+        .collect()
+}
+
+/// Read all `<LineString>` and `<gx:Track>` elements of a KML document, in document order.
+pub fn read_kml(data: &[u8]) -> Result<Vec<Vec<TrackPoint>>, String> {
+    let parser = EventReader::new(std::io::Cursor::new(data));
+
+    let mut path: Vec<String> = Vec::new();
+    let mut lines: Vec<Vec<TrackPoint>> = Vec::new();
+    // gx:Track lists all timestamps before the coordinates they belong to
+    let mut times: Vec<DateTime<Utc>> = Vec::new();
+
+    for event in parser {
+        match event.map_err(|err| err.to_string())? {
+            XmlEvent::StartElement { name, .. } => {
+                if name.local_name == "Track" {
+                    lines.push(Vec::new());
+                    times.clear();
+                }
+                path.push(name.local_name);
+            }
+            XmlEvent::EndElement { .. } => {
+                path.pop();
+            }
+            XmlEvent::Characters(text) => {
+                let element = path.last().map(|s| s.as_str());
+                let parent = path.iter().rev().nth(1).map(|s| s.as_str());
+                match (parent, element) {
+                    (Some("LineString"), Some("coordinates")) => {
+                        lines.push(read_coordinates(&text)?)
+                    }
+                    (Some("Track"), Some("when")) => {
+                        let time = DateTime::parse_from_rfc3339(text.trim())
+                            .map_err(|err| format!("invalid time {}: {}", text, err))?;
+                        times.push(time.with_timezone(&Utc));
+                    }
+                    (Some("Track"), Some("coord")) => {
+                        // gx:coord separates values with spaces instead of commas
+                        let tuple = text.split_whitespace().collect::<Vec<&str>>().join(",");
+                        let mut points = read_coordinates(&tuple)?;
+                        if let (Some(line), Some(mut point)) = (lines.last_mut(), points.pop()) {
+                            point.time = times.get(line.len()).cloned();
+                            line.push(point);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_strings_are_read_in_order() {
+        let data = br#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+  <Document>
+    <Placemark><LineString><coordinates>
+      17.9,62.5,10 17.91,62.51,12
+    </coordinates></LineString></Placemark>
+    <Placemark><LineString><coordinates>17.92,62.52 17.93,62.53</coordinates></LineString></Placemark>
+  </Document>
+</kml>"#;
+
+        let lines = read_kml(data).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), 2);
+        assert_eq!((lines[0][0].lat, lines[0][0].lon), (62.5, 17.9));
+        assert_eq!(lines[0][1].elevation, Some(12.0));
+        assert_eq!(lines[1][0].elevation, None);
+        assert!(lines[0][0].time.is_none());
+    }
+
+    #[test]
+    fn tracks_are_timed() {
+        let data = br#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Placemark><gx:Track>
+    <when>2017-07-30T08:00:00Z</when>
+    <when>2017-07-30T08:00:05Z</when>
+    <gx:coord>17.9 62.5 10</gx:coord>
+    <gx:coord>17.91 62.51 12</gx:coord>
+  </gx:Track></Placemark>
+</kml>"#;
+
+        let lines = read_kml(data).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), 2);
+        assert_eq!((lines[0][1].lat, lines[0][1].lon), (62.51, 17.91));
+        assert_eq!(
+            lines[0][1].time,
+            Some(Utc.ymd(2017, 7, 30).and_hms(8, 0, 5))
+        );
+    }
+
+    #[test]
+    fn invalid_coordinates_are_an_error() {
+        let data = br#"<kml><Placemark><LineString><coordinates>17.9</coordinates></LineString></Placemark></kml>"#;
+        assert!(read_kml(data).is_err());
+    }
+}
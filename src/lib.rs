@@ -5,6 +5,10 @@ extern crate gpx;
 extern crate postgis;
 extern crate postgres;
 extern crate serde;
+extern crate serde_json;
+extern crate sha2;
+extern crate toml;
+extern crate xml;
 
 use chrono::prelude::*;
 use dotenv::dotenv;
@@ -13,14 +17,47 @@ use std::env;
 use geo::algorithm::vincenty_distance::VincentyDistance;
 
 use postgis::ewkb;
-use postgres::{Connection, TlsMode};
+use postgres::{Connection, GenericConnection, TlsMode};
 
 use serde::{Deserialize, Serialize};
 
-use geo::Coordinate;
-use postgis::ewkb::{LineStringZ, PointZ};
 use std::fs::File;
 use std::io::prelude::*;
+use std::time::Instant;
+
+pub mod compare;
+pub mod duplicate;
+pub mod elevation;
+pub mod error;
+pub mod export;
+pub mod fit;
+pub mod formats;
+pub mod geojson;
+pub mod gpx_extensions;
+pub mod kml;
+pub mod manifest;
+pub mod sectors;
+pub mod sensors;
+pub mod simplify;
+pub mod speed;
+pub mod tcx;
+pub mod timing;
+pub mod track;
+pub mod validation;
+
+use compare::{compare_progress, equal_sectors, split_point_sectors, Comparison};
+use duplicate::{Duplicate, DuplicatePolicy, DuplicateReason, SAME_START_SECONDS};
+use elevation::{climb_stats, elevation_profile, ClimbStats, ProfilePoint};
+pub use error::Error;
+use formats::read_track;
+use sectors::{sector_times, SectorLeader, SectorPosition, SegmentSector};
+use sensors::{SensorSeries, SensorSummary};
+use sha2::{Digest, Sha256};
+use simplify::{simplify_track, SimplifyOptions};
+use speed::{distance_time_series, speed_stats, DistanceTime, SpeedStats};
+use timing::{compute_total, EventTiming, TimedStage, TimingMode};
+use track::{NamedTrack, TrackPoint};
+use validation::{validate_track, ValidationReport};
 
 pub fn read_whole_file(path: &str) -> Result<String, std::io::Error> {
     let mut file = File::open(path)?;
@@ -29,6 +66,13 @@ pub fn read_whole_file(path: &str) -> Result<String, std::io::Error> {
     return Ok(contents);
 }
 
+pub fn read_whole_file_bytes(path: &str) -> Result<Vec<u8>, std::io::Error> {
+    let mut file = File::open(path)?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    return Ok(contents);
+}
+
 pub fn read_gpx(gpx_data: &str) -> Result<gpx::Gpx, gpx::errors::Error> {
     let reader = std::io::Cursor::new(gpx_data.as_bytes());
 
@@ -43,15 +87,67 @@ pub fn establish_connection() -> Connection {
 }
 
 const EMPTY_DB_SQL: &'static str = include_str!("empty_db.sql");
-const CREATE_DB_SQL: &'static str = include_str!("create_db.sql");
 
+/// Schema migrations in the order they are applied. Never edit a migration that has been
+/// released, add a new one instead.
+const MIGRATIONS: &'static [(i32, &'static str)] = &[
+    (1, include_str!("migrations/001_initial.sql")),
+    (2, include_str!("migrations/002_event_manifest.sql")),
+    (3, include_str!("migrations/003_ordered_stages.sql")),
+    (4, include_str!("migrations/004_activities.sql")),
+    (5, include_str!("migrations/005_spatial_indexes.sql")),
+    (6, include_str!("migrations/006_raw_tracks.sql")),
+    (7, include_str!("migrations/007_uploads.sql")),
+    (8, include_str!("migrations/008_duplicates.sql")),
+    (9, include_str!("migrations/009_sensors.sql")),
+    (10, include_str!("migrations/010_segment_elevation.sql")),
+    (11, include_str!("migrations/011_split_speed.sql")),
+    (12, include_str!("migrations/012_segment_sectors.sql")),
+    (13, include_str!("migrations/013_timing_modes.sql")),
+    (14, include_str!("migrations/014_progress_seconds.sql")),
+];
+
+/// Apply all migrations that have not yet been applied to the database, each in its own
+/// transaction. Returns the number of migrations applied.
+pub fn migrate_db(db: &Connection) -> Result<usize, postgres::Error> {
+    db.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )?;
+
+    let rows = db.query("SELECT version FROM schema_migrations", &[])?;
+    let applied: Vec<i32> = rows.iter().map(|row| row.get("version")).collect();
+
+    let mut count = 0;
+    for &(version, sql) in MIGRATIONS {
+        if applied.contains(&version) {
+            continue;
+        }
+
+        let trans = db.transaction()?;
+        trans.batch_execute(sql)?;
+        trans.execute(
+            "INSERT INTO schema_migrations (version) VALUES ($1)",
+            &[&version],
+        )?;
+        trans.commit()?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Create an empty database from scratch, dropping all existing data.
 pub fn create_db(db: &Connection) -> Result<(), postgres::Error> {
     match db.batch_execute(EMPTY_DB_SQL) {
         Ok(_) => (),
         Err(_) => (),
     }
 
-    db.batch_execute(CREATE_DB_SQL)
+    migrate_db(db)?;
+    Ok(())
 }
 
 pub fn empty_db(db: &Connection) -> Result<(), postgres::Error> {
@@ -81,7 +177,7 @@ pub fn get_users(db: &Connection) -> Option<Vec<User>> {
     }
 }
 
-pub fn create_user(db: &Connection, name: &str, email: &str) -> Option<User> {
+pub fn create_user(db: &GenericConnection, name: &str, email: &str) -> Option<User> {
     match db.query(
         "INSERT INTO users (name, email) VALUES ($1, $2) RETURNING id",
         &[&name, &email],
@@ -106,73 +202,570 @@ pub fn get_user(db: &Connection, user_id: i64) -> Option<User> {
     }
 }
 
-pub fn create_segment(db: &Connection, name: &str, waypoints: &[gpx::Waypoint]) -> Option<i64> {
-    let points = waypoints
+pub fn get_user_by_email(db: &GenericConnection, email: &str) -> Option<User> {
+    match db.query("SELECT * FROM users WHERE email = $1", &[&email]) {
+        Ok(ref rows) if !rows.is_empty() => Some(User {
+            id: rows.get(0).get("id"),
+            name: rows.get(0).get("name"),
+            email: rows.get(0).get("email"),
+        }),
+        _ => None,
+    }
+}
+
+/// A file as it was uploaded, stored with whatever is created from it.
+pub struct UploadFile<'a> {
+    pub data: &'a [u8],
+    pub filename: Option<&'a str>,
+    pub uploader_id: Option<i64>,
+}
+
+/// The hex encoded SHA-256 hash identifying the contents of an uploaded file.
+pub fn hash_upload(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn store_upload(db: &GenericConnection, file: &UploadFile) -> Result<i64, postgres::Error> {
+    let rows = db.query(
+        "INSERT INTO uploads (sha256, filename, uploader_id, data) VALUES ($1, $2, $3, $4) RETURNING id",
+        &[&hash_upload(file.data), &file.filename, &file.uploader_id, &file.data],
+    )?;
+
+    Ok(rows.get(0).get(0))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Upload {
+    pub id: i64,
+    pub sha256: String,
+    pub filename: Option<String>,
+    pub uploaded_at: DateTime<Utc>,
+    pub uploader_id: Option<i64>,
+    /// Size of the file in bytes
+    pub size: i32,
+}
+
+pub fn get_upload(db: &Connection, upload_id: i64) -> Option<Upload> {
+    match db.query(
+        "SELECT id, sha256, filename, uploaded_at, uploader_id, octet_length(data) AS size
+        FROM uploads WHERE id = $1",
+        &[&upload_id],
+    ) {
+        Ok(ref rows) if !rows.is_empty() => {
+            let row = rows.get(0);
+            Some(Upload {
+                id: row.get("id"),
+                sha256: row.get("sha256"),
+                filename: row.get("filename"),
+                uploaded_at: row.get("uploaded_at"),
+                uploader_id: row.get("uploader_id"),
+                size: row.get("size"),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The contents of an uploaded file.
+pub fn get_upload_data(db: &Connection, upload_id: i64) -> Option<Vec<u8>> {
+    match db.query("SELECT data FROM uploads WHERE id = $1", &[&upload_id]) {
+        Ok(ref rows) if !rows.is_empty() => Some(rows.get(0).get("data")),
+        _ => None,
+    }
+}
+
+/// The uploaded file a participation or segment was created from, `table` is one of the two.
+fn get_upload_data_for(
+    db: &Connection,
+    table: &str,
+    id: i64,
+) -> Result<Option<Vec<u8>>, postgres::Error> {
+    let rows = db.query(
+        &format!(
+            "SELECT uploads.data FROM {0} INNER JOIN uploads ON uploads.id = {0}.upload_id WHERE {0}.id = $1",
+            table
+        ),
+        &[&id],
+    )?;
+
+    Ok(if rows.is_empty() {
+        None
+    } else {
+        Some(rows.get(0).get("data"))
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Segment {
+    pub id: i64,
+    pub name: String,
+    /// Length in meters
+    pub length: f64,
+    pub tolerance: f64,
+    /// The file the segment was created from
+    pub upload_id: Option<i64>,
+    /// Unset if the segment line has no elevation
+    pub climb: Option<ClimbStats>,
+}
+
+const SEGMENT_COLUMNS: &'static str = "id, name, tolerance, upload_id, ST_Length(geom) AS length,
+    ascent, descent, min_elevation, max_elevation, avg_gradient";
+
+fn segment_from_row(row: &postgres::rows::Row) -> Segment {
+    let ascent: Option<f64> = row.get("ascent");
+    Segment {
+        id: row.get("id"),
+        name: row.get("name"),
+        length: row.get("length"),
+        tolerance: row.get("tolerance"),
+        upload_id: row.get("upload_id"),
+        climb: ascent.map(|ascent| ClimbStats {
+            ascent,
+            descent: row.get("descent"),
+            min_elevation: row.get("min_elevation"),
+            max_elevation: row.get("max_elevation"),
+            avg_gradient: row.get("avg_gradient"),
+        }),
+    }
+}
+
+pub fn get_segments(db: &Connection) -> Option<Vec<Segment>> {
+    match db.query(
+        &format!("SELECT {} FROM segments ORDER BY id", SEGMENT_COLUMNS),
+        &[],
+    ) {
+        Ok(rows) => Some(rows.iter().map(|row| segment_from_row(&row)).collect()),
+        Err(_) => None,
+    }
+}
+
+pub fn get_segment(db: &Connection, segment_id: i64) -> Option<Segment> {
+    match db.query(
+        &format!("SELECT {} FROM segments WHERE id = $1", SEGMENT_COLUMNS),
+        &[&segment_id],
+    ) {
+        Ok(ref rows) if !rows.is_empty() => Some(segment_from_row(&rows.get(0))),
+        _ => None,
+    }
+}
+
+/// Get the line of a segment with its elevation, e.g. to export it to a file.
+pub fn get_segment_track(db: &Connection, segment_id: i64) -> Option<Vec<TrackPoint>> {
+    match db.query(
+        "SELECT geom, elevations FROM segments WHERE id = $1",
+        &[&segment_id],
+    ) {
+        Ok(ref rows) if !rows.is_empty() => {
+            let line: ewkb::LineString = rows.get(0).get("geom");
+            let elevations: Option<Vec<Option<f64>>> = rows.get(0).get("elevations");
+            let elevations = elevations.unwrap_or_default();
+            Some(
+                line.points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| TrackPoint {
+                        lat: p.y,
+                        lon: p.x,
+                        elevation: elevations.get(i).and_then(|&e| e),
+                        time: None,
+                        heart_rate: None,
+                        cadence: None,
+                        temperature: None,
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Elevation against distance along a segment, for charts. Empty if the segment line has no
+/// elevation.
+pub fn get_segment_profile(db: &Connection, segment_id: i64) -> Option<Vec<ProfilePoint>> {
+    get_segment_track(db, segment_id).map(|track| elevation_profile(&track))
+}
+
+/// Distance in meters a ride may deviate from a segment and still match it, unless the segment
+/// says otherwise.
+pub const DEFAULT_TOLERANCE: f64 = 20.0;
+
+fn track_to_segment_line(track: &[TrackPoint]) -> ewkb::LineString {
+    let points = track
         .iter()
-        .map(|wp| {
-            let p = wp.point();
-            ewkb::Point {
-                x: p.x(),
-                y: p.y(),
-                srid: Some(4326),
-            }
+        .map(|tp| ewkb::Point {
+            x: tp.lon,
+            y: tp.lat,
+            srid: Some(4326),
         })
         .collect::<Vec<ewkb::Point>>();
-    let line = ewkb::LineString {
+
+    ewkb::LineString {
         points,
         srid: Some(4326),
+    }
+}
+
+/// Store the elevation of every point of a segment line and the climbing statistics computed from
+/// it.
+fn store_segment_elevation(
+    db: &GenericConnection,
+    segment_id: i64,
+    track: &[TrackPoint],
+) -> Result<(), postgres::Error> {
+    let elevations = track
+        .iter()
+        .map(|tp| tp.elevation)
+        .collect::<Vec<Option<f64>>>();
+    let stats = climb_stats(&elevation_profile(track));
+    db.execute(
+        "UPDATE segments SET elevations = $2, ascent = $3, descent = $4, min_elevation = $5, max_elevation = $6, avg_gradient = $7
+        WHERE id = $1",
+        &[
+            &segment_id,
+            &elevations,
+            &stats.map(|s| s.ascent),
+            &stats.map(|s| s.descent),
+            &stats.map(|s| s.min_elevation),
+            &stats.map(|s| s.max_elevation),
+            &stats.map(|s| s.avg_gradient),
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Create a segment from a line, e.g. a track or a line drawn in a mapping tool. Positions and
+/// elevation are used, timestamps are ignored. `tolerance` is how far in meters a ride may deviate from the
+/// line and still be timed on it. The file the line was read from, if any, is stored with it.
+/// Earlier activities passing through the segment are timed on it.
+pub fn create_segment(
+    db: &GenericConnection,
+    name: &str,
+    tolerance: f64,
+    track: &[TrackPoint],
+    upload: Option<&UploadFile>,
+) -> Result<i64, postgres::Error> {
+    let trans = db.transaction()?;
+
+    let upload_id = match upload {
+        Some(file) => Some(store_upload(&trans, file)?),
+        None => None,
     };
 
-    match db.query(
-        "INSERT INTO segments (name, geom) VALUES ($1, $2) RETURNING id",
-        &[&name, &line],
-    ) {
-        Ok(rows) => {
-            let segment_id: i64 = rows.get(0).get(0);
+    // The buffered polygon is computed in the same statement so a segment never exists without it
+    let rows = trans.query(
+        "INSERT INTO segments (name, tolerance, geom, geom_expanded, upload_id)
+        VALUES ($1, $2, $3::geography, ST_Buffer($3::geography, $2, 'endcap=flat join=round'), $4)
+        RETURNING id",
+        &[&name, &tolerance, &track_to_segment_line(track), &upload_id],
+    )?;
+    let segment_id: i64 = rows.get(0).get(0);
+    store_segment_elevation(&trans, segment_id, track)?;
+    retime_segment(&trans, segment_id)?;
 
-            // TODO: do this on insert and skip a query
-            db.execute(
-                "UPDATE segments SET geom_expanded = ST_Buffer(geom, 20, 'endcap=flat join=round') WHERE id = $1",
-                &[&segment_id],
-            ).unwrap();
+    trans.commit()?;
 
-            Some(segment_id)
-        }
-        Err(err) => None,
+    Ok(segment_id)
+}
+
+/// Rebuild the line of a segment from its uploaded file and retime everything using it. Returns
+/// false if the segment was not created from an uploaded file.
+pub fn reprocess_segment(db: &Connection, segment_id: i64) -> Result<bool, Error> {
+    let data = match get_upload_data_for(db, "segments", segment_id)? {
+        Some(data) => data,
+        None => return Ok(false),
+    };
+    let track = read_track(&data)?;
+
+    let trans = db.transaction()?;
+    trans.execute(
+        "UPDATE segments SET geom = $2::geography,
+            geom_expanded = ST_Buffer($2::geography, tolerance, 'endcap=flat join=round')
+        WHERE id = $1",
+        &[&segment_id, &track_to_segment_line(&track.points)],
+    )?;
+    store_segment_elevation(&trans, segment_id, &track.points)?;
+    retime_segment(&trans, segment_id)?;
+    trans.commit()?;
+
+    Ok(true)
+}
+
+pub fn create_event(
+    db: &GenericConnection,
+    name: &str,
+    date: Option<NaiveDate>,
+    segment_ids: &[i64],
+) -> Result<i64, postgres::Error> {
+    let trans = db.transaction()?;
+
+    let rows = trans.query(
+        "INSERT INTO events (name, date) VALUES ($1, $2) RETURNING id",
+        &[&name, &date],
+    )?;
+    let event_id: i64 = rows.get(0).get(0);
+
+    // Stages are numbered in the order the segments are given
+    for (stage_index, segment_id) in segment_ids.iter().enumerate() {
+        trans.execute(
+            "INSERT INTO event_segments (event_id, segment_id, stage_index) VALUES ($1, $2, $3)",
+            &[&event_id, &segment_id, &(stage_index as i32)],
+        )?;
     }
+
+    trans.commit()?;
+
+    Ok(event_id)
+}
+
+pub fn add_event_category(
+    db: &GenericConnection,
+    event_id: i64,
+    name: &str,
+) -> Result<(), postgres::Error> {
+    db.execute(
+        "INSERT INTO event_categories (event_id, name) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        &[&event_id, &name],
+    )?;
+
+    Ok(())
 }
 
-pub fn create_event(db: &Connection, name: &str, segment_ids: &[i64]) -> i64 {
+pub fn get_event_categories(db: &Connection, event_id: i64) -> Vec<String> {
+    db.query(
+        "SELECT name FROM event_categories WHERE event_id = $1 ORDER BY name",
+        &[&event_id],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get("name"))
+    .collect()
+}
+
+/// Register a rider for an event, or change the category of an already registered rider.
+pub fn register_rider(
+    db: &GenericConnection,
+    event_id: i64,
+    user_id: i64,
+    category: Option<&str>,
+) -> Result<(), postgres::Error> {
+    db.execute(
+        "INSERT INTO event_riders (event_id, user_id, category) VALUES ($1, $2, $3)
+        ON CONFLICT (event_id, user_id) DO UPDATE SET category = EXCLUDED.category",
+        &[&event_id, &user_id, &category],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventRider {
+    pub user: User,
+    pub category: Option<String>,
+    /// The participation of the rider that counts in the event, if any
+    pub participation_id: Option<i64>,
+}
+
+/// Get the riders of an event: those registered for it and those who uploaded a participation
+/// without registering, who have no category.
+pub fn get_event_riders(db: &Connection, event_id: i64) -> Vec<EventRider> {
     let rows = db
         .query(
-            "INSERT INTO events (name) VALUES ($1) RETURNING id",
-            &[&name],
+            "SELECT users.id, users.name, users.email, event_riders.category,
+                (SELECT MAX(participations.id) FROM participations
+                WHERE participations.event_id = $1 AND participations.user_id = users.id AND participations.counted) AS participation_id
+            FROM users
+            LEFT JOIN event_riders ON event_riders.event_id = $1 AND event_riders.user_id = users.id
+            WHERE event_riders.user_id IS NOT NULL
+                OR EXISTS (SELECT 1 FROM participations WHERE participations.event_id = $1 AND participations.user_id = users.id)
+            ORDER BY users.name",
+            &[&event_id],
         )
         .unwrap();
-    let event_id: i64 = rows.get(0).get(0);
 
-    for segment_id in segment_ids {
-        db.execute(
-            "INSERT INTO event_segments (event_id, segment_id) VALUES ($1, $2)",
-            &[&event_id, &segment_id],
-        )
-        .unwrap();
+    rows.iter()
+        .map(|row| EventRider {
+            user: User {
+                id: row.get("id"),
+                name: row.get("name"),
+                email: row.get("email"),
+            },
+            category: row.get("category"),
+            participation_id: row.get("participation_id"),
+        })
+        .collect()
+}
+
+fn retime_event(db: &GenericConnection, event_id: i64) -> Result<(), postgres::Error> {
+    let participation_rows = db.query(
+        "SELECT id FROM participations WHERE event_id = $1",
+        &[&event_id],
+    )?;
+    for row in &participation_rows {
+        let participation_id: i64 = row.get("id");
+        update_participation_timing(db, participation_id)?;
+    }
+
+    Ok(())
+}
+
+/// Add a segment to an existing event as its last stage, retiming all participations in the
+/// event.
+pub fn add_event_segment(
+    db: &Connection,
+    event_id: i64,
+    segment_id: i64,
+) -> Result<(), postgres::Error> {
+    let trans = db.transaction()?;
+
+    trans.execute(
+        "INSERT INTO event_segments (event_id, segment_id, stage_index)
+        SELECT $1, $2, COALESCE(MAX(stage_index) + 1, 0) FROM event_segments WHERE event_id = $1",
+        &[&event_id, &segment_id],
+    )?;
+    retime_event(&trans, event_id)?;
+
+    trans.commit()
+}
+
+/// Name a stage of an event, or clear the name to fall back to the name of the segment. Returns
+/// false if the segment is not a stage of the event.
+pub fn set_stage_name(
+    db: &GenericConnection,
+    event_id: i64,
+    segment_id: i64,
+    name: Option<&str>,
+) -> Result<bool, postgres::Error> {
+    let updated = db.execute(
+        "UPDATE event_segments SET stage_name = $1 WHERE event_id = $2 AND segment_id = $3",
+        &[&name, &event_id, &segment_id],
+    )?;
+
+    Ok(updated > 0)
+}
+
+/// Require the stages of an event to be ridden in stage order, retiming all participations in
+/// the event.
+pub fn set_stages_in_order(
+    db: &GenericConnection,
+    event_id: i64,
+    in_order: bool,
+) -> Result<(), postgres::Error> {
+    let trans = db.transaction()?;
+
+    trans.execute(
+        "UPDATE events SET stages_in_order = $1 WHERE id = $2",
+        &[&in_order, &event_id],
+    )?;
+    retime_event(&trans, event_id)?;
+
+    trans.commit()
+}
+
+/// Choose how the total time of an event is computed, with the official start for mass start
+/// events, retiming all participations in the event. Returns false if there is no such event.
+pub fn set_event_timing(
+    db: &GenericConnection,
+    event_id: i64,
+    timing: &EventTiming,
+) -> Result<bool, postgres::Error> {
+    let trans = db.transaction()?;
+
+    let updated = trans.execute(
+        "UPDATE events SET timing_mode = $1, mass_start = $2, late_penalty = $3 WHERE id = $4",
+        &[
+            &timing.mode.name(),
+            &timing.mass_start,
+            &timing.late_penalty,
+            &event_id,
+        ],
+    )?;
+    if updated == 0 {
+        return Ok(false);
+    }
+    retime_event(&trans, event_id)?;
+
+    trans.commit()?;
+
+    Ok(true)
+}
+
+/// Limit the time allowed to reach the start of a stage, or remove the limit, retiming all
+/// participations in the event. Returns false if the segment is not a stage of the event.
+pub fn set_liaison_cutoff(
+    db: &GenericConnection,
+    event_id: i64,
+    segment_id: i64,
+    cutoff: Option<f64>,
+) -> Result<bool, postgres::Error> {
+    let trans = db.transaction()?;
+
+    let updated = trans.execute(
+        "UPDATE event_segments SET liaison_cutoff = $1 WHERE event_id = $2 AND segment_id = $3",
+        &[&cutoff, &event_id, &segment_id],
+    )?;
+    if updated == 0 {
+        return Ok(false);
+    }
+    retime_event(&trans, event_id)?;
+
+    trans.commit()?;
+    Ok(true)
+}
+
+/// The time window in which stage attempts count for an event, either end can be left open.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+pub struct EventWindow {
+    pub open: Option<DateTime<Utc>>,
+    pub close: Option<DateTime<Utc>>,
+}
+
+/// Restrict the time window in which stage attempts count for an event, retiming all
+/// participations in the event. Returns false if the event does not exist.
+pub fn set_event_window(
+    db: &GenericConnection,
+    event_id: i64,
+    window: &EventWindow,
+) -> Result<bool, postgres::Error> {
+    let trans = db.transaction()?;
+
+    let updated = trans.execute(
+        "UPDATE events SET start_window_open = $1, start_window_close = $2 WHERE id = $3",
+        &[&window.open, &window.close, &event_id],
+    )?;
+    if updated == 0 {
+        return Ok(false);
     }
+    retime_event(&trans, event_id)?;
 
-    event_id
+    trans.commit()?;
+    Ok(true)
 }
 
 struct SegmentMatch {
     pub elapsed: f64,
+    /// Seconds from the start of the participation to the start of the attempt
+    pub start: f64,
+    /// Seconds from the start of the participation to the end of the attempt
+    pub finish: f64,
+    /// The part of the participation that was timed
+    pub geom: ewkb::LineStringZ,
+    /// `geom` with the time between chained parts left out, like the elapsed time
+    pub continuous: ewkb::LineStringZ,
+    /// Time through each sector, unset if the segment has no split points or one was missed
+    pub sectors: Option<Vec<f64>>,
 }
 
 struct SegmentInfo {
     pub segment_id: i64,
+    /// Seconds allowed to reach the start of the stage, for events timed with liaisons
+    pub liaison_cutoff: Option<f64>,
     pub matches: Vec<SegmentMatch>,
 }
 
 fn check_dist(p1: &ewkb::Point, p2: &ewkb::PointZ, threshold: f64) -> bool {
-    if p1.x == p1.x && p1.y == p1.y {
+    if p1.x == p2.x && p1.y == p2.y {
         // we have to test for point equality here because vincenty_distance fails when given
         // two identical points
         true
@@ -186,318 +779,1876 @@ fn check_dist(p1: &ewkb::Point, p2: &ewkb::PointZ, threshold: f64) -> bool {
     }
 }
 
-fn check_line(line: &ewkb::LineStringZ, start: &ewkb::Point, end: &ewkb::Point) -> Option<f64> {
-    let ls = &line.points[0];
-    let le = &line.points[line.points.len() - 1];
-    match check_dist(start, ls, 20.0) && check_dist(end, le, 20.0) {
-        true => Some(le.z - ls.z),
-        false => None,
+fn join_lines(lines: &[ewkb::LineStringZ]) -> ewkb::LineStringZ {
+    ewkb::LineStringZ {
+        points: lines
+            .iter()
+            .flat_map(|line| line.points.iter().cloned())
+            .collect(),
+        srid: Some(4326),
+    }
+}
+
+/// Join connected parts of a participation like `join_lines`, shifting the time of every part to
+/// continue where the previous one ended.
+fn join_lines_without_gaps(lines: &[ewkb::LineStringZ]) -> ewkb::LineStringZ {
+    let mut points = Vec::new();
+    let mut offset = 0.0;
+    for line in lines {
+        let first = match line.points.first() {
+            Some(first) => first.z,
+            None => continue,
+        };
+        if let Some(last) = points.last().map(|p: &ewkb::PointZ| p.z) {
+            offset = last - first;
+        }
+        points.extend(line.points.iter().map(|p| ewkb::PointZ {
+            x: p.x,
+            y: p.y,
+            z: p.z + offset,
+            srid: p.srid,
+        }));
+    }
+
+    ewkb::LineStringZ {
+        points,
+        srid: Some(4326),
     }
 }
 
+/// Find every attempt at a segment in the parts of a participation that lie within the segment's
+/// expanded polygon. An attempt starts at a part beginning near the segment start and chains
+/// connected parts until one ends near the segment end.
 fn match_segments(
-    db: &postgres::Connection,
-    lines: &Vec<ewkb::LineStringZ>,
+    lines: &[ewkb::LineStringZ],
     segment_start: &ewkb::Point,
     segment_end: &ewkb::Point,
-    pid: i64,
-    sid: i64,
-) -> Option<f64> {
-    if lines.len() == 0 {
-        return None;
-    }
+    split_points: &[ewkb::Point],
+    tolerance: f64,
+) -> Vec<SegmentMatch> {
+    let mut matches = Vec::new();
 
-    let mut total_time: f64 = 0.0;
     let mut start_line_index = 0;
-    let mut last_end = ewkb::Point {
-        x: 0.0,
-        y: 0.0,
-        srid: None,
-    };
+    while start_line_index < lines.len() {
+        if !check_dist(segment_start, &lines[start_line_index].points[0], tolerance) {
+            start_line_index += 1;
+            continue;
+        }
 
-    // Find suitable start point in a segment
-    loop {
-        let line = &lines[start_line_index];
-        let points = &line.points;
-        let start = &points[0];
-        let end = points.last().unwrap();
-        if check_dist(segment_start, start, 20.0) {
-            total_time += line.points[line.points.len() - 1].z - line.points[0].z;
-
-            // if end distance also matches here we are done!
-            if check_dist(segment_end, end, 20.0) {
-                return Some(total_time);
+        let mut end_line_index = start_line_index;
+        let mut completed = false;
+        loop {
+            let end = lines[end_line_index].points.last().unwrap();
+            if check_dist(segment_end, end, tolerance) {
+                completed = true;
+                break;
             }
 
-            // This segment matched a start but not the end
-            // we store the initial point and try to chain it to the next
-            last_end = ewkb::Point {
+            // The next line has to connect with this one to continue the attempt
+            let last_end = ewkb::Point {
                 x: end.x,
                 y: end.y,
                 srid: end.srid,
             };
-            break;
+            if end_line_index + 1 >= lines.len()
+                || !check_dist(&last_end, &lines[end_line_index + 1].points[0], tolerance)
+            {
+                break;
+            }
+            end_line_index += 1;
         }
 
-        start_line_index += 1;
-        if start_line_index >= lines.len() {
-            return None;
+        if !completed {
+            // Look for a later start
+            start_line_index += 1;
+            continue;
         }
-    }
 
-    let mut end_line_index = start_line_index + 1;
-
-    // start match was last line, no match
-    if end_line_index == lines.len() {
-        return None;
+        let attempt = &lines[start_line_index..end_line_index + 1];
+        let geom = join_lines(attempt);
+        let sectors = if split_points.is_empty() {
+            None
+        } else {
+            sector_times(attempt, split_points, tolerance)
+        };
+        matches.push(SegmentMatch {
+            elapsed: attempt
+                .iter()
+                .map(|line| line.points[line.points.len() - 1].z - line.points[0].z)
+                .sum(),
+            start: attempt[0].points[0].z,
+            finish: attempt[attempt.len() - 1].points.last().unwrap().z,
+            geom,
+            continuous: join_lines_without_gaps(attempt),
+            sectors,
+        });
+        start_line_index = end_line_index + 1;
     }
 
-    loop {
-        let line = &lines[end_line_index];
-        let points = &line.points;
-        let start = &points[0];
-        let end = points.last().unwrap();
+    matches
+}
 
-        // current line did not connect with previous
-        // no match for now
-        // TODO: restart searching for a match from the next line as start
-        if !check_dist(&last_end, start, 20.0) {
-            return None;
-        }
+/// Pick the fastest attempt of each stage.
+fn select_fastest(stages: &[SegmentInfo]) -> Vec<Option<usize>> {
+    stages
+        .iter()
+        .map(|stage| {
+            let mut fastest: Option<usize> = None;
+            for (i, segment_match) in stage.matches.iter().enumerate() {
+                if fastest.map_or(true, |f| segment_match.elapsed < stage.matches[f].elapsed) {
+                    fastest = Some(i);
+                }
+            }
+            fastest
+        })
+        .collect()
+}
 
-        total_time += line.points[line.points.len() - 1].z - line.points[0].z;
+fn is_better_selection(a: (usize, f64), b: (usize, f64)) -> bool {
+    a.0 > b.0 || (a.0 == b.0 && a.1 < b.1)
+}
 
-        // does this line complete the segment?
-        if check_dist(segment_end, end, 20.0) {
-            return Some(total_time);
-        }
+/// Pick at most one attempt per stage such that the picked attempts were ridden in stage order,
+/// timing as many stages as possible and, among those, with the lowest total time.
+fn select_in_order(stages: &[SegmentInfo]) -> Vec<Option<usize>> {
+    // For every attempt, the best selection ending with it: the number of stages timed, their
+    // total time and the attempt picked for the previous timed stage
+    let mut best: Vec<Vec<(usize, f64, Option<(usize, usize)>)>> = Vec::new();
+    for (stage_index, stage) in stages.iter().enumerate() {
+        let selections = stage
+            .matches
+            .iter()
+            .map(|segment_match| {
+                let mut selection = (1, segment_match.elapsed, None);
+                for previous_index in 0..stage_index {
+                    for (i, previous) in stages[previous_index].matches.iter().enumerate() {
+                        if previous.finish > segment_match.start {
+                            continue;
+                        }
+
+                        let (count, elapsed, _) = best[previous_index][i];
+                        let candidate = (
+                            count + 1,
+                            elapsed + segment_match.elapsed,
+                            Some((previous_index, i)),
+                        );
+                        if is_better_selection(
+                            (candidate.0, candidate.1),
+                            (selection.0, selection.1),
+                        ) {
+                            selection = candidate;
+                        }
+                    }
+                }
+                selection
+            })
+            .collect();
+        best.push(selections);
+    }
 
-        end_line_index += 1;
-        if end_line_index >= lines.len() {
-            return None;
+    let mut last: Option<(usize, usize)> = None;
+    let mut last_selection = (0, 0.0);
+    for (stage_index, selections) in best.iter().enumerate() {
+        for (i, &(count, elapsed, _)) in selections.iter().enumerate() {
+            if is_better_selection((count, elapsed), last_selection) {
+                last = Some((stage_index, i));
+                last_selection = (count, elapsed);
+            }
         }
+    }
 
-        last_end = ewkb::Point {
-            x: end.x,
-            y: end.y,
-            srid: end.srid,
-        };
+    let mut picked = vec![None; stages.len()];
+    while let Some((stage_index, i)) = last {
+        picked[stage_index] = Some(i);
+        last = best[stage_index][i].2;
     }
+    picked
 }
 
-fn update_participation_timing(db: &Connection, participation_id: i64) {
-    // TODO: to this whole thing in the DB
-    let participation_rows = db
-        .query(
-            "SELECT * FROM participations WHERE id = $1",
-            &[&participation_id],
-        )
-        .unwrap();
+fn offset_time(start_time: DateTime<Utc>, seconds: f64) -> DateTime<Utc> {
+    start_time + chrono::Duration::milliseconds((seconds * 1000.0).round() as i64)
+}
 
-    let event_id: i64 = participation_rows.get(0).get("event_id");
+const SEGMENT_CUT_COLUMNS: &'static str = "segments.id,
+    segments.tolerance,
+    CASE WHEN segments.geom_expanded && participation.geom THEN
+        ST_Multi(ST_CollectionExtract(ST_Intersection(segments.geom_expanded, participation.geom)::geometry, 2))
+    END AS cut,
+    ST_StartPoint(segments.geom::geometry) AS segment_start,
+    ST_EndPoint(segments.geom::geometry) AS segment_end,
+    ARRAY(SELECT ST_X(geom::geometry) FROM segment_sectors WHERE segment_id = segments.id ORDER BY distance) AS split_lon,
+    ARRAY(SELECT ST_Y(geom::geometry) FROM segment_sectors WHERE segment_id = segments.id ORDER BY distance) AS split_lat";
+
+/// Read the timing settings of an event from a row with its `timing_mode`, `mass_start` and
+/// `late_penalty`. Rows without an event get the default settings.
+fn event_timing_from_row(row: &postgres::rows::Row) -> EventTiming {
+    let mode: Option<String> = row.get("timing_mode");
+    let late_penalty: Option<f64> = row.get("late_penalty");
+    let default = EventTiming::default();
+    EventTiming {
+        mode: mode
+            .and_then(|mode| TimingMode::from_name(&mode))
+            .unwrap_or_default(),
+        mass_start: row.get("mass_start"),
+        late_penalty: late_penalty.unwrap_or(default.late_penalty),
+    }
+}
 
-    // TODO: handle the case where the user submits many attempts on a single event
-    let count_rows = db
-        .query(
-            "SELECT COUNT(segment_id) FROM participation_segments WHERE participation_id = $1",
+/// Time a participation on its event's stages, or on every segment it passes for activities.
+/// Returns false if there is no such participation.
+fn update_participation_timing(
+    db: &GenericConnection,
+    participation_id: i64,
+) -> Result<bool, postgres::Error> {
+    // TODO: to this whole thing in the DB
+    let participation_rows = db.query(
+        "SELECT participations.event_id, participations.user_id, participations.start_time, events.start_window_open, events.start_window_close, events.stages_in_order,
+            events.timing_mode, events.mass_start, events.late_penalty
+        FROM participations
+        LEFT JOIN events ON events.id = participations.event_id
+        WHERE participations.id = $1",
+        &[&participation_id],
+    )?;
+    if participation_rows.is_empty() {
+        return Ok(false);
+    }
+
+    // Activities have no event, they are timed on every segment they pass through
+    let event_id: Option<i64> = participation_rows.get(0).get("event_id");
+    let user_id: i64 = participation_rows.get(0).get("user_id");
+    let start_time: DateTime<Utc> = participation_rows.get(0).get("start_time");
+    let window_open: Option<DateTime<Utc>> = participation_rows.get(0).get("start_window_open");
+    let window_close: Option<DateTime<Utc>> = participation_rows.get(0).get("start_window_close");
+    let stages_in_order: Option<bool> = participation_rows.get(0).get("stages_in_order");
+    let timing = event_timing_from_row(&participation_rows.get(0));
+
+    // Throw away any splits from a previous run, they are recomputed from scratch below
+    db.execute(
+        "DELETE FROM participation_segments WHERE participation_id = $1",
+        &[&participation_id],
+    )?;
+
+    // All segments are cut in one query. The bounding box check skips the intersection for
+    // segments the ride does not come near, and the result is always a, possibly empty,
+    // multi line string.
+    let segment_rows = match event_id {
+        Some(event_id) => db.query(
+            &format!(
+                "SELECT {}, event_segments.liaison_cutoff
+                FROM segments
+                INNER JOIN event_segments ON (event_segments.event_id = $2 AND segments.id = event_segments.segment_id)
+                CROSS JOIN (SELECT geom FROM participations WHERE id = $1) AS participation
+                ORDER BY event_segments.stage_index",
+                SEGMENT_CUT_COLUMNS
+            ),
+            &[&participation_id, &event_id],
+        )?,
+        // The index on segments.geom finds the segments near the ride, of those only the ones
+        // whose start and end both lie on the ride can be matched
+        None => db.query(
+            &format!(
+                "SELECT {}, NULL::DOUBLE PRECISION AS liaison_cutoff
+                FROM segments, (SELECT geom FROM participations WHERE id = $1) AS participation
+                WHERE ST_DWithin(segments.geom, participation.geom, segments.tolerance)
+                    AND ST_DWithin(ST_StartPoint(segments.geom::geometry)::geography, participation.geom, segments.tolerance)
+                    AND ST_DWithin(ST_EndPoint(segments.geom::geometry)::geography, participation.geom, segments.tolerance)
+                ORDER BY segments.id",
+                SEGMENT_CUT_COLUMNS
+            ),
             &[&participation_id],
-        )
-        .unwrap();
-    let old_count: i64 = count_rows.get(0).get(0);
-    if old_count > 0 {
-        println!(
-            "Participation already has all data set! Need to implement multi attempt support..."
-        );
-        return;
-    }
-
-    let segment_rows = db.query("SELECT
-                                    *
-                                 FROM
-                                    segments
-                                 INNER JOIN
-                                    event_segments
-                                 ON (event_segments.event_id = $1 AND segments.id = event_segments.segment_id)",
-                                 &[&event_id])
-        .unwrap();
+        )?,
+    };
 
     let mut matched_segments: Vec<SegmentInfo> = Vec::new();
 
     for row in &segment_rows {
         let segment_id: i64 = row.get("id");
+        let tolerance: f64 = row.get("tolerance");
+        let segment_start: ewkb::Point = row.get("segment_start");
+        let segment_end: ewkb::Point = row.get("segment_end");
+        let cut: Option<ewkb::MultiLineStringZ> = row.get("cut");
+        let lines = cut.map_or(Vec::new(), |mls| mls.lines);
+        let split_lon: Vec<f64> = row.get("split_lon");
+        let split_lat: Vec<f64> = row.get("split_lat");
+        let split_points = split_lon
+            .into_iter()
+            .zip(split_lat)
+            .map(|(x, y)| ewkb::Point {
+                x,
+                y,
+                srid: Some(4326),
+            })
+            .collect::<Vec<ewkb::Point>>();
 
         let mut segment_info = SegmentInfo {
             segment_id,
+            liaison_cutoff: row.get("liaison_cutoff"),
             matches: Vec::new(),
         };
 
-        let matched_rows = db.query("SELECT
-                                        ST_Intersection(segment.geom_expanded, participation.geom) AS cut,
-                                        ST_StartPoint(segment.geom::geometry) AS segment_start,
-                                        ST_EndPoint(segment.geom::geometry) AS segment_end
-                                    FROM
-                                    (SELECT geom FROM participations WHERE id = $1) AS participation,
-                                    (SELECT geom, geom_expanded FROM segments WHERE id = $2) AS segment",
-                             &[&participation_id, &segment_id],
-        ).unwrap();
-
-        let segment_start: ewkb::Point = matched_rows.get(0).get("segment_start");
-        let segment_end: ewkb::Point = matched_rows.get(0).get("segment_end");
-
-        let is_mls: Option<postgres::Result<ewkb::MultiLineStringZ>> =
-            matched_rows.get(0).get_opt("cut");
-        let lines = match is_mls {
-            Some(Ok(mls)) => mls.lines,
-            Some(Err(..)) => {
-                let ls: ewkb::LineStringZ = matched_rows.get(0).get("cut");
-                vec![ls]
-            }
-            None => vec![],
-        };
-
-        match match_segments(
-            &db,
+        for segment_match in match_segments(
             &lines,
             &segment_start,
             &segment_end,
-            participation_id,
-            segment_id,
+            &split_points,
+            tolerance,
         ) {
-            Some(seconds) => {
-                let segment_match = SegmentMatch { elapsed: seconds };
+            // Attempts started outside of the event time window do not count
+            let started_at = offset_time(start_time, segment_match.start);
+            let too_early = window_open.map_or(false, |open| started_at < open);
+            let too_late = window_close.map_or(false, |close| started_at > close);
+            if !too_early && !too_late {
                 segment_info.matches.push(segment_match);
             }
-            None => (),
         }
 
         matched_segments.push(segment_info);
     }
 
-    // TODO: more advanced completion logic
-    // for now we just make sure all segments are matched, and take the fastest time
-    let mut total_elapsed: f64 = 0.0;
-    let mut total_valid: usize = 0;
-    for segment_info in matched_segments {
-        let valid = segment_info.matches.len() != 0;
-        let mut smallest: f64 = std::f64::MAX;
-        for segment_match in segment_info.matches {
-            if segment_match.elapsed < smallest {
-                smallest = segment_match.elapsed;
-            }
-        }
+    // A participation is complete when all stages are timed. Activities keep every effort on a
+    // segment.
+    let picked: Vec<Vec<usize>> = if event_id.is_none() {
+        matched_segments
+            .iter()
+            .map(|segment_info| (0..segment_info.matches.len()).collect())
+            .collect()
+    } else {
+        let selection = if stages_in_order.unwrap_or(false) {
+            select_in_order(&matched_segments)
+        } else {
+            select_fastest(&matched_segments)
+        };
+        selection
+            .into_iter()
+            .map(|picked| picked.into_iter().collect())
+            .collect()
+    };
 
-        if valid {
-            total_elapsed += smallest;
-            total_valid += 1;
+    let sensors = get_participation_sensors(db, participation_id)?;
+
+    let mut timed_stages: Vec<TimedStage> = Vec::new();
+    for (segment_info, picked) in matched_segments.iter().zip(picked) {
+        for segment_match in picked.into_iter().map(|i| &segment_info.matches[i]) {
+            let started_at = offset_time(start_time, segment_match.start);
+            let finished_at = offset_time(start_time, segment_match.finish);
+            let summary = sensors.as_ref().map_or(SensorSummary::default(), |series| {
+                series.summarize(segment_match.start, segment_match.finish)
+            });
+            let speed = speed_stats(&segment_match.continuous);
+            let series = distance_time_series(&segment_match.continuous);
+            let series_distance = series.iter().map(|s| s.distance).collect::<Vec<f64>>();
+            let series_seconds = series.iter().map(|s| s.seconds).collect::<Vec<f64>>();
+            let point_seconds = segment_match
+                .continuous
+                .points
+                .iter()
+                .map(|p| p.z - segment_match.start)
+                .collect::<Vec<f64>>();
+            db.execute(
+                "INSERT INTO participation_segments (participation_id, segment_id, elapsed_seconds, started_at, finished_at, geom, avg_heart_rate, max_heart_rate, avg_cadence, avg_temperature, distance, avg_speed, max_speed, moving_seconds, stopped_seconds, series_distance, series_seconds, sector_seconds, point_seconds) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+                &[&participation_id, &segment_info.segment_id, &segment_match.elapsed, &started_at, &finished_at, &segment_match.geom, &summary.avg_heart_rate, &summary.max_heart_rate, &summary.avg_cadence, &summary.avg_temperature, &speed.distance, &speed.avg_speed, &speed.max_speed, &speed.moving_seconds, &speed.stopped_seconds, &series_distance, &series_seconds, &segment_match.sectors, &point_seconds],
+            )?;
+
+            timed_stages.push(TimedStage {
+                elapsed: segment_match.elapsed,
+                started_at,
+                finished_at,
+                liaison_cutoff: segment_info.liaison_cutoff,
+            });
         }
     }
 
-    if total_valid == segment_rows.len() {
-        // TODO: update this from DB instead of from here
-        db.execute(
-            "UPDATE participations SET total_elapsed_seconds = $1
-            WHERE id = $2",
-            &[&total_elapsed, &participation_id],
-        )
-        .unwrap();
+    // An incomplete participation gets no total, this also clears totals from earlier runs.
+    // Activities have no total either, only efforts on segments.
+    let total = if event_id.is_some() && timed_stages.len() == segment_rows.len() {
+        Some(compute_total(&timing, &timed_stages))
+    } else {
+        None
+    };
+
+    // TODO: update this from DB instead of from here
+    db.execute(
+        "UPDATE participations SET total_elapsed_seconds = $1, penalty_seconds = $2
+        WHERE id = $3",
+        &[
+            &total.map(|t| t.elapsed),
+            &total.map(|t| t.penalty),
+            &participation_id,
+        ],
+    )?;
+
+    // A new total can change which participation of the rider counts
+    if let Some(event_id) = event_id {
+        update_counted_participation(db, event_id, user_id)?;
     }
+
+    Ok(true)
 }
 
-pub fn create_participation(
+/// Recompute the splits and total time of a single participation, replacing any earlier result.
+/// Returns false if there is no such participation.
+pub fn recompute_participation(
     db: &Connection,
-    event_id: i64,
-    user_id: i64,
-    waypoints: &[gpx::Waypoint],
-) -> i64 {
-    let start_time = waypoints[0].time.unwrap().timestamp_millis();
-    let points = waypoints
-        .iter()
-        .map(|wp| {
-            let p = wp.point();
-            ewkb::PointZ {
-                x: p.x(),
-                y: p.y(),
-                z: (wp.time.unwrap().timestamp_millis() - start_time) as f64 / 1000.0,
-                srid: Some(4326),
-            }
-        })
-        .collect::<Vec<ewkb::PointZ>>();
-    let line = ewkb::LineStringZ {
-        points,
-        srid: Some(4326),
-    };
-
-    let rows = db
-        .query(
-            "INSERT INTO participations (event_id, user_id, geom) VALUES ($1, $2, $3) RETURNING id",
-            &[&event_id, &user_id, &line],
-        )
-        .unwrap();
-    let participation_id: i64 = rows.get(0).get(0);
-
-    update_participation_timing(db, participation_id);
+    participation_id: i64,
+) -> Result<bool, postgres::Error> {
+    let trans = db.transaction()?;
+    if !update_participation_timing(&trans, participation_id)? {
+        return Ok(false);
+    }
+    trans.commit()?;
 
-    participation_id
+    Ok(true)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct EventResult {
-    pub username: String,
-    pub time: f64,
+fn recompute_participations(
+    db: &Connection,
+    participation_rows: &postgres::rows::Rows,
+) -> Result<usize, postgres::Error> {
+    let trans = db.transaction()?;
+    for row in participation_rows {
+        let participation_id: i64 = row.get("id");
+        update_participation_timing(&trans, participation_id)?;
+    }
+    trans.commit()?;
+
+    Ok(participation_rows.len())
 }
 
-pub fn get_event_results(db: &Connection, event_id: i64) -> Vec<EventResult> {
-    let event_rows = db.query(
-        "SELECT * FROM participations INNER JOIN users ON participations.event_id = $1 AND users.id = participations.user_id ORDER BY participations.total_elapsed_seconds ASC",
+/// Recompute all participations in an event. Returns the number of participations updated.
+pub fn recompute_event(db: &Connection, event_id: i64) -> Result<usize, postgres::Error> {
+    let participation_rows = db.query(
+        "SELECT id FROM participations WHERE event_id = $1",
         &[&event_id],
-    ).unwrap();
+    )?;
 
-    event_rows
-        .iter()
-        .map(|row| {
-            let username: String = row.get("name");
-            let maybe_elapsed: Option<postgres::Result<f64>> = row.get_opt("total_elapsed_seconds");
-            let time = match maybe_elapsed {
-                Some(Ok(elapsed)) => elapsed,
-                Some(Err(..)) | None => 0.0,
-            };
+    recompute_participations(db, &participation_rows)
+}
 
-            EventResult { username, time }
-        })
-        .collect()
+/// Recompute all participations in events that use a segment, all activities passing near it and
+/// all activities timed on it before, e.g. after its geometry changed or when it was just
+/// created. Returns the number of participations updated.
+pub fn recompute_segment(db: &Connection, segment_id: i64) -> Result<usize, postgres::Error> {
+    let trans = db.transaction()?;
+    let count = retime_segment(&trans, segment_id)?;
+    trans.commit()?;
+
+    Ok(count)
+}
+
+fn retime_segment(db: &GenericConnection, segment_id: i64) -> Result<usize, postgres::Error> {
+    // Activities timed on the segment before are included so efforts on its old geometry are
+    // dropped when they no longer match
+    let participation_rows = db.query(
+        "SELECT participations.id FROM participations
+        INNER JOIN event_segments ON event_segments.event_id = participations.event_id
+        WHERE event_segments.segment_id = $1
+        UNION
+        SELECT participations.id FROM participations, segments
+        WHERE participations.event_id IS NULL AND segments.id = $1
+            AND ST_DWithin(segments.geom, participations.geom, segments.tolerance)
+        UNION
+        SELECT participation_id FROM participation_segments WHERE segment_id = $1",
+        &[&segment_id],
+    )?;
+    for row in &participation_rows {
+        let participation_id: i64 = row.get("id");
+        update_participation_timing(db, participation_id)?;
+    }
+
+    Ok(participation_rows.len())
+}
+
+/// Validate a participation track against the event it is uploaded to, without storing anything.
+pub fn validate_participation(
+    db: &GenericConnection,
+    event_id: i64,
+    track: &[TrackPoint],
+) -> Result<ValidationReport, Error> {
+    let rows = db.query(
+        "SELECT date, start_window_open, start_window_close FROM events WHERE id = $1",
+        &[&event_id],
+    )?;
+    if rows.is_empty() {
+        return Err(Error::NotFound(format!("event {}", event_id)));
+    }
+    let event_date: Option<NaiveDate> = rows.get(0).get("date");
+    let window_open: Option<DateTime<Utc>> = rows.get(0).get("start_window_open");
+    let window_close: Option<DateTime<Utc>> = rows.get(0).get("start_window_close");
+
+    Ok(validate_track(track, event_date, window_open, window_close))
+}
+
+/// Delete a participation and its splits. Returns false if there was no such participation.
+pub fn delete_participation(
+    db: &Connection,
+    participation_id: i64,
+) -> Result<bool, postgres::Error> {
+    let trans = db.transaction()?;
+
+    let rows = trans.query(
+        "DELETE FROM participations WHERE id = $1 RETURNING event_id, user_id",
+        &[&participation_id],
+    )?;
+    if rows.is_empty() {
+        return Ok(false);
+    }
+
+    // Another participation of the rider may count now
+    let event_id: Option<i64> = rows.get(0).get("event_id");
+    if let Some(event_id) = event_id {
+        update_counted_participation(&trans, event_id, rows.get(0).get("user_id"))?;
+    }
+
+    trans.commit()?;
+
+    Ok(true)
+}
+
+/// Get the duplicate policy of an event, `None` if there is no such event.
+fn get_duplicate_policy(
+    db: &GenericConnection,
+    event_id: i64,
+) -> Result<Option<DuplicatePolicy>, postgres::Error> {
+    let rows = db.query(
+        "SELECT duplicate_policy FROM events WHERE id = $1",
+        &[&event_id],
+    )?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    let name: String = rows.get(0).get("duplicate_policy");
+
+    Ok(Some(DuplicatePolicy::from_name(&name).unwrap_or_default()))
+}
+
+/// Set which participation of a rider counts in an event, updating the results right away.
+pub fn set_duplicate_policy(
+    db: &GenericConnection,
+    event_id: i64,
+    policy: DuplicatePolicy,
+) -> Result<(), postgres::Error> {
+    let trans = db.transaction()?;
+
+    trans.execute(
+        "UPDATE events SET duplicate_policy = $1 WHERE id = $2",
+        &[&policy.name(), &event_id],
+    )?;
+
+    let rows = trans.query(
+        "SELECT DISTINCT user_id FROM participations WHERE event_id = $1",
+        &[&event_id],
+    )?;
+    for row in &rows {
+        update_counted_participation(&trans, event_id, row.get("user_id"))?;
+    }
+
+    trans.commit()
+}
+
+/// Mark the participation of a rider that counts in the results of an event according to the
+/// event's duplicate policy, all others are left out.
+fn update_counted_participation(
+    db: &GenericConnection,
+    event_id: i64,
+    user_id: i64,
+) -> Result<(), postgres::Error> {
+    let order = match get_duplicate_policy(db, event_id)? {
+        Some(DuplicatePolicy::Reject) | Some(DuplicatePolicy::Replace) => "id DESC",
+        Some(DuplicatePolicy::KeepBest) => "total_elapsed_seconds ASC NULLS LAST, id",
+        None => return Ok(()),
+    };
+
+    let rows = db.query(
+        &format!(
+            "SELECT id FROM participations WHERE event_id = $1 AND user_id = $2 ORDER BY {} LIMIT 1",
+            order
+        ),
+        &[&event_id, &user_id],
+    )?;
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let counted_id: i64 = rows.get(0).get("id");
+
+    // Clear the old one first, only one participation per rider may count at any time
+    db.execute(
+        "UPDATE participations SET counted = FALSE
+        WHERE event_id = $1 AND user_id = $2 AND id <> $3 AND counted",
+        &[&event_id, &user_id, &counted_id],
+    )?;
+    db.execute(
+        "UPDATE participations SET counted = TRUE WHERE id = $1",
+        &[&counted_id],
+    )?;
+
+    Ok(())
+}
+
+/// Look for an earlier upload of the same ride by the same rider, in the same event or among
+/// their activities, and for an earlier entry when the event rejects further participations.
+pub fn find_duplicate(
+    db: &GenericConnection,
+    event_id: Option<i64>,
+    user_id: i64,
+    track: &[TrackPoint],
+    upload: Option<&UploadFile>,
+) -> Result<Option<Duplicate>, postgres::Error> {
+    if let Some(file) = upload {
+        let rows = db.query(
+            "SELECT participations.id FROM participations
+            INNER JOIN uploads ON uploads.id = participations.upload_id
+            WHERE uploads.sha256 = $1 AND participations.user_id = $2
+                AND participations.event_id IS NOT DISTINCT FROM $3
+            ORDER BY participations.id LIMIT 1",
+            &[&hash_upload(file.data), &user_id, &event_id],
+        )?;
+        if !rows.is_empty() {
+            return Ok(Some(Duplicate {
+                participation_id: rows.get(0).get("id"),
+                reason: DuplicateReason::SameFile,
+            }));
+        }
+    }
+
+    if let Some(start) = track.first().and_then(|point| point.time) {
+        let rows = db.query(
+            "SELECT id FROM participations
+            WHERE user_id = $1 AND event_id IS NOT DISTINCT FROM $2
+                AND abs(extract(epoch FROM start_time - $3::TIMESTAMPTZ))::DOUBLE PRECISION < $4
+            ORDER BY id LIMIT 1",
+            &[&user_id, &event_id, &start, &SAME_START_SECONDS],
+        )?;
+        if !rows.is_empty() {
+            return Ok(Some(Duplicate {
+                participation_id: rows.get(0).get("id"),
+                reason: DuplicateReason::SameStart,
+            }));
+        }
+    }
+
+    if let Some(event_id) = event_id {
+        if get_duplicate_policy(db, event_id)? == Some(DuplicatePolicy::Reject) {
+            let rows = db.query(
+                "SELECT id FROM participations WHERE event_id = $1 AND user_id = $2 AND counted",
+                &[&event_id, &user_id],
+            )?;
+            if !rows.is_empty() {
+                return Ok(Some(Duplicate {
+                    participation_id: rows.get(0).get("id"),
+                    reason: DuplicateReason::AlreadyEntered,
+                }));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Upload a ride to an event, storing the file it was read from if given.
+pub fn create_participation(
+    db: &GenericConnection,
+    event_id: i64,
+    user_id: i64,
+    track: &[TrackPoint],
+    options: &SimplifyOptions,
+    upload: Option<&UploadFile>,
+) -> Result<i64, Error> {
+    let report = validate_participation(db, event_id, track)?;
+    if !report.is_valid() {
+        return Err(Error::Invalid(report));
+    }
+    if let Some(duplicate) = find_duplicate(db, Some(event_id), user_id, track, upload)? {
+        return Err(Error::Duplicate(duplicate));
+    }
+
+    Ok(insert_participation(
+        db,
+        Some(event_id),
+        user_id,
+        track,
+        options,
+        upload,
+    )?)
+}
+
+/// Upload a ride that is not part of an event, it is timed on every known segment it passes
+/// through.
+pub fn create_activity(
+    db: &Connection,
+    user_id: i64,
+    track: &[TrackPoint],
+    options: &SimplifyOptions,
+    upload: Option<&UploadFile>,
+) -> Result<i64, Error> {
+    let report = validate_track(track, None, None, None);
+    if !report.is_valid() {
+        return Err(Error::Invalid(report));
+    }
+    if let Some(duplicate) = find_duplicate(db, None, user_id, track, upload)? {
+        return Err(Error::Duplicate(duplicate));
+    }
+
+    Ok(insert_participation(
+        db, None, user_id, track, options, upload,
+    )?)
+}
+
+fn track_to_line(track: &[TrackPoint], start: DateTime<Utc>) -> ewkb::LineStringZ {
+    let start_time = start.timestamp_millis();
+    let points = track
+        .iter()
+        .map(|tp| ewkb::PointZ {
+            x: tp.lon,
+            y: tp.lat,
+            z: (tp.time.unwrap().timestamp_millis() - start_time) as f64 / 1000.0,
+            srid: Some(4326),
+        })
+        .collect::<Vec<ewkb::PointZ>>();
+
+    ewkb::LineStringZ {
+        points,
+        srid: Some(4326),
+    }
+}
+
+/// Store the uploaded track of a participation along with the simplified track it is matched
+/// on, without timing it.
+fn store_participation(
+    db: &GenericConnection,
+    event_id: Option<i64>,
+    user_id: i64,
+    raw: &[TrackPoint],
+    simplified: &[TrackPoint],
+    upload_id: Option<i64>,
+) -> Result<i64, postgres::Error> {
+    let start = raw[0].time.unwrap();
+    // Participations in events start out uncounted, timing them decides which one counts
+    let counted = event_id.is_none();
+    let rows = db.query(
+        "INSERT INTO participations (event_id, user_id, start_time, geom, raw_geom, upload_id, counted) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        &[&event_id, &user_id, &start, &track_to_line(simplified, start), &track_to_line(raw, start), &upload_id, &counted],
+    )?;
+    let participation_id = rows.get(0).get(0);
+    store_participation_sensors(db, participation_id, raw)?;
+
+    Ok(participation_id)
+}
+
+/// Store the sensor readings of the uploaded track, replacing any stored before. Tracks without
+/// readings store nothing.
+fn store_participation_sensors(
+    db: &GenericConnection,
+    participation_id: i64,
+    track: &[TrackPoint],
+) -> Result<(), postgres::Error> {
+    db.execute(
+        "DELETE FROM participation_sensors WHERE participation_id = $1",
+        &[&participation_id],
+    )?;
+
+    if let Some(series) = SensorSeries::from_track(track) {
+        db.execute(
+            "INSERT INTO participation_sensors (participation_id, seconds, heart_rate, cadence, temperature) VALUES ($1, $2, $3, $4, $5)",
+            &[&participation_id, &series.seconds, &series.heart_rate, &series.cadence, &series.temperature],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn get_participation_sensors(
+    db: &GenericConnection,
+    participation_id: i64,
+) -> Result<Option<SensorSeries>, postgres::Error> {
+    let rows = db.query(
+        "SELECT seconds, heart_rate, cadence, temperature FROM participation_sensors WHERE participation_id = $1",
+        &[&participation_id],
+    )?;
+
+    Ok(rows.iter().next().map(|row| SensorSeries {
+        seconds: row.get("seconds"),
+        heart_rate: row.get("heart_rate"),
+        cadence: row.get("cadence"),
+        temperature: row.get("temperature"),
+    }))
+}
+
+fn insert_participation(
+    db: &GenericConnection,
+    event_id: Option<i64>,
+    user_id: i64,
+    track: &[TrackPoint],
+    options: &SimplifyOptions,
+    upload: Option<&UploadFile>,
+) -> Result<i64, postgres::Error> {
+    let simplified = simplify_track(track, options);
+
+    // Insert and timing happen in one transaction so a failure never leaves a participation
+    // without a result
+    let trans = db.transaction()?;
+
+    let upload_id = match upload {
+        Some(file) => Some(store_upload(&trans, file)?),
+        None => None,
+    };
+    let participation_id =
+        store_participation(&trans, event_id, user_id, track, &simplified, upload_id)?;
+    update_participation_timing(&trans, participation_id)?;
+
+    trans.commit()?;
+
+    Ok(participation_id)
+}
+
+/// Rebuild a participation from its uploaded file, e.g. after fixing a file reader or to apply
+/// different simplification, and retime it. Returns false if the participation was not created
+/// from an uploaded file.
+pub fn reprocess_participation(
+    db: &Connection,
+    participation_id: i64,
+    options: &SimplifyOptions,
+) -> Result<bool, Error> {
+    let data = match get_upload_data_for(db, "participations", participation_id)? {
+        Some(data) => data,
+        None => return Ok(false),
+    };
+    let track = read_track(&data)?;
+    let report = validate_track(&track.points, None, None, None);
+    if !report.is_valid() {
+        return Err(Error::Invalid(report));
+    }
+
+    let simplified = simplify_track(&track.points, options);
+    let start = track.points[0].time.unwrap();
+
+    let trans = db.transaction()?;
+    trans.execute(
+        "UPDATE participations SET start_time = $2, geom = $3, raw_geom = $4 WHERE id = $1",
+        &[
+            &participation_id,
+            &start,
+            &track_to_line(&simplified, start),
+            &track_to_line(&track.points, start),
+        ],
+    )?;
+    store_participation_sensors(&trans, participation_id, &track.points)?;
+    update_participation_timing(&trans, participation_id)?;
+    trans.commit()?;
+
+    Ok(true)
+}
+
+/// Rebuild all participations with an uploaded file, or only those in one event. Returns the
+/// number of participations rebuilt.
+pub fn reprocess_participations(
+    db: &Connection,
+    event_id: Option<i64>,
+    options: &SimplifyOptions,
+) -> Result<usize, Error> {
+    let rows = db.query(
+        "SELECT id FROM participations
+        WHERE upload_id IS NOT NULL AND ($1::BIGINT IS NULL OR event_id = $1)
+        ORDER BY id",
+        &[&event_id],
+    )?;
+
+    let mut count = 0;
+    for row in &rows {
+        if reprocess_participation(db, row.get("id"), options)? {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Matching of a track preprocessed one way.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MatchingRun {
+    /// Number of points matched
+    pub points: usize,
+    /// Time matching took
+    pub seconds: f64,
+    /// Elapsed time of every timed attempt, by segment and start
+    pub attempts: Vec<f64>,
+}
+
+/// Matching of the same track before and after simplification.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MatchingBenchmark {
+    pub raw: MatchingRun,
+    pub simplified: MatchingRun,
+}
+
+impl MatchingBenchmark {
+    /// Largest difference in seconds between the attempts timed on the raw and the simplified
+    /// track, None if they timed different attempts.
+    pub fn max_difference(&self) -> Option<f64> {
+        if self.raw.attempts.len() != self.simplified.attempts.len() {
+            return None;
+        }
+
+        Some(
+            self.raw
+                .attempts
+                .iter()
+                .zip(&self.simplified.attempts)
+                .map(|(raw, simplified)| (raw - simplified).abs())
+                .fold(0.0, f64::max),
+        )
+    }
+}
+
+fn benchmark_run(
+    db: &Connection,
+    event_id: Option<i64>,
+    user_id: i64,
+    track: &[TrackPoint],
+    options: &SimplifyOptions,
+) -> Result<MatchingRun, postgres::Error> {
+    let simplified = simplify_track(track, options);
+
+    // The transaction is never committed
+    let trans = db.transaction()?;
+    let participation_id =
+        store_participation(&trans, event_id, user_id, track, &simplified, None)?;
+
+    let started = Instant::now();
+    update_participation_timing(&trans, participation_id)?;
+    let elapsed = started.elapsed();
+
+    let rows = trans.query(
+        "SELECT elapsed_seconds FROM participation_segments
+        WHERE participation_id = $1 AND elapsed_seconds IS NOT NULL
+        ORDER BY segment_id, started_at",
+        &[&participation_id],
+    )?;
+
+    Ok(MatchingRun {
+        points: simplified.len(),
+        seconds: elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9,
+        attempts: rows.iter().map(|row| row.get("elapsed_seconds")).collect(),
+    })
+}
+
+/// Time how long matching a track takes as uploaded and after preprocessing it with the given
+/// options, along with the attempts timed each way, without storing anything.
+pub fn benchmark_matching(
+    db: &Connection,
+    event_id: Option<i64>,
+    user_id: i64,
+    track: &[TrackPoint],
+    options: &SimplifyOptions,
+) -> Result<MatchingBenchmark, Error> {
+    let report = match event_id {
+        Some(event_id) => validate_participation(db, event_id, track)?,
+        None => validate_track(track, None, None, None),
+    };
+    if !report.is_valid() {
+        return Err(Error::Invalid(report));
+    }
+
+    Ok(MatchingBenchmark {
+        raw: benchmark_run(db, event_id, user_id, track, &SimplifyOptions::default())?,
+        simplified: benchmark_run(db, event_id, user_id, track, options)?,
+    })
+}
+
+fn line_to_track(line: &ewkb::LineStringZ, start_time: DateTime<Utc>) -> Vec<TrackPoint> {
+    line.points
+        .iter()
+        .map(|p| TrackPoint {
+            lat: p.y,
+            lon: p.x,
+            elevation: None,
+            time: Some(offset_time(start_time, p.z)),
+            heart_rate: None,
+            cadence: None,
+            temperature: None,
+        })
+        .collect()
+}
+
+/// Get the full track of a participation followed by one track per timed stage attempt, holding
+/// the part of the ride that was matched to the stage with its original timestamps.
+pub fn get_matched_tracks(db: &Connection, participation_id: i64) -> Option<Vec<NamedTrack>> {
+    let participation_rows = db
+        .query(
+            "SELECT participations.start_time, COALESCE(participations.raw_geom, participations.geom) AS geom, users.name
+            FROM participations
+            INNER JOIN users ON users.id = participations.user_id
+            WHERE participations.id = $1",
+            &[&participation_id],
+        )
+        .unwrap();
+    if participation_rows.is_empty() {
+        return None;
+    }
+
+    let row = participation_rows.get(0);
+    let start_time: DateTime<Utc> = row.get("start_time");
+    let geom: ewkb::LineStringZ = row.get("geom");
+    let username: String = row.get("name");
+
+    let mut tracks = vec![NamedTrack {
+        name: username,
+        points: line_to_track(&geom, start_time),
+    }];
+
+    let split_rows = db
+        .query(
+            "SELECT COALESCE(event_segments.stage_name, segments.name) AS name, participation_segments.geom
+            FROM participation_segments
+            INNER JOIN participations ON participations.id = participation_segments.participation_id
+            INNER JOIN segments ON segments.id = participation_segments.segment_id
+            LEFT JOIN event_segments ON event_segments.event_id = participations.event_id
+                AND event_segments.segment_id = participation_segments.segment_id
+            WHERE participation_segments.participation_id = $1 AND participation_segments.geom IS NOT NULL
+            ORDER BY event_segments.stage_index, participation_segments.started_at",
+            &[&participation_id],
+        )
+        .unwrap();
+    for row in &split_rows {
+        let cut: ewkb::LineStringZ = row.get("geom");
+        tracks.push(NamedTrack {
+            name: row.get("name"),
+            points: line_to_track(&cut, start_time),
+        });
+    }
+
+    Some(tracks)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StageResult {
+    pub segment_id: i64,
+    pub name: String,
+    pub elapsed: Option<f64>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Heart rate, cadence and temperature during the attempt
+    pub sensors: SensorSummary,
+    /// Unset for stages not timed, or timed before speeds were computed
+    pub speed: Option<SpeedStats>,
+    /// Time through each sector of the segment, unset if it has none or one was missed
+    pub sectors: Option<Vec<f64>>,
+}
+
+fn row_to_sensor_summary(row: &postgres::rows::Row) -> SensorSummary {
+    SensorSummary {
+        avg_heart_rate: row.get("avg_heart_rate"),
+        max_heart_rate: row.get("max_heart_rate"),
+        avg_cadence: row.get("avg_cadence"),
+        avg_temperature: row.get("avg_temperature"),
+    }
+}
+
+fn row_to_speed_stats(row: &postgres::rows::Row) -> Option<SpeedStats> {
+    let distance: Option<f64> = row.get("distance");
+    distance.map(|distance| SpeedStats {
+        distance,
+        avg_speed: row.get("avg_speed"),
+        max_speed: row.get("max_speed"),
+        moving_seconds: row.get("moving_seconds"),
+        stopped_seconds: row.get("stopped_seconds"),
+    })
+}
+
+/// Get the time taken to reach each distance along a timed stage attempt, e.g. to chart it or
+/// compare riders, the fastest of an activity with several efforts on the segment. None if the
+/// stage was not timed, or timed before the series was stored.
+pub fn get_split_series(
+    db: &Connection,
+    participation_id: i64,
+    segment_id: i64,
+) -> Option<Vec<DistanceTime>> {
+    let rows = db
+        .query(
+            "SELECT series_distance, series_seconds FROM participation_segments
+            WHERE participation_id = $1 AND segment_id = $2
+            ORDER BY elapsed_seconds, started_at LIMIT 1",
+            &[&participation_id, &segment_id],
+        )
+        .unwrap();
+    if rows.is_empty() {
+        return None;
+    }
+
+    let row = rows.get(0);
+    let distances: Option<Vec<f64>> = row.get("series_distance");
+    let seconds: Option<Vec<f64>> = row.get("series_seconds");
+    match (distances, seconds) {
+        (Some(distances), Some(seconds)) => Some(
+            distances
+                .into_iter()
+                .zip(seconds)
+                .map(|(distance, seconds)| DistanceTime { distance, seconds })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Get the time taken to reach each point of a timed stage attempt, located along the segment
+/// line. Activities with several efforts on the segment use the fastest. Like the elapsed time,
+/// the time between chained parts of the attempt is left out, except for splits timed before
+/// that was stored. None if the stage was not timed in the participation.
+fn get_segment_progress(
+    db: &Connection,
+    participation_id: i64,
+    segment_id: i64,
+) -> Result<Option<Vec<DistanceTime>>, postgres::Error> {
+    let rows = db.query(
+        "SELECT ST_LineLocatePoint(segments.geom::geometry, ST_Force2D(point.geom)) * ST_Length(segments.geom) AS distance,
+            COALESCE(
+                participation_segments.point_seconds[point.path[1]],
+                ST_Z(point.geom) - ST_Z(ST_StartPoint(participation_segments.geom::geometry))
+            ) AS seconds
+        FROM participation_segments
+        INNER JOIN segments ON segments.id = participation_segments.segment_id
+        CROSS JOIN LATERAL ST_DumpPoints(participation_segments.geom::geometry) AS point
+        WHERE participation_segments.participation_id = $1 AND participation_segments.segment_id = $2
+            AND participation_segments.started_at = (
+                SELECT started_at FROM participation_segments
+                WHERE participation_id = $1 AND segment_id = $2
+                ORDER BY elapsed_seconds, started_at LIMIT 1
+            )
+        ORDER BY point.path[1]",
+        &[&participation_id, &segment_id],
+    )?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        rows.iter()
+            .map(|row| DistanceTime {
+                distance: row.get("distance"),
+                seconds: row.get("seconds"),
+            })
+            .collect(),
+    ))
+}
+
+/// Compare two attempts on a segment, showing where along it the second rider lost or gained
+/// time on the first. Sectors are those between the split points of the segment, or `sectors`
+/// equal parts if it has none. None if the segment does not exist or either participation was
+/// not timed on it.
+pub fn compare_attempts(
+    db: &Connection,
+    segment_id: i64,
+    participation_a: i64,
+    participation_b: i64,
+    sectors: usize,
+) -> Result<Option<Comparison>, postgres::Error> {
+    let segment = match get_segment(db, segment_id) {
+        Some(segment) => segment,
+        None => return Ok(None),
+    };
+    let a = get_segment_progress(db, participation_a, segment_id)?;
+    let b = get_segment_progress(db, participation_b, segment_id)?;
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Ok(None),
+    };
+
+    let split_points = get_segment_sectors(db, segment_id)
+        .iter()
+        .map(|sector| sector.distance)
+        .collect::<Vec<f64>>();
+    let boundaries = if split_points.is_empty() {
+        equal_sectors(segment.length, sectors)
+    } else {
+        split_point_sectors(segment.length, &split_points)
+    };
+
+    let (gaps, sectors) = compare_progress(segment.length, &a, &b, &boundaries);
+    Ok(Some(Comparison {
+        segment_id,
+        length: segment.length,
+        participation_a,
+        participation_b,
+        gaps,
+        sectors,
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventResult {
+    pub participation_id: i64,
+    pub username: String,
+    pub category: Option<String>,
+    /// Total time including any penalty
+    pub time: f64,
+    /// Seconds added for arriving late at stages
+    pub penalty: Option<f64>,
+    pub start_time: Option<DateTime<Utc>>,
+    /// The file the participation was created from
+    pub upload_id: Option<i64>,
+    pub stages: Vec<StageResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventStage {
+    /// Position of the stage in the event, starting at 0
+    pub index: i32,
+    pub segment_id: i64,
+    /// The stage name, or the segment name for unnamed stages
+    pub name: String,
+    /// Seconds allowed to reach the start of the stage, for events timed with liaisons
+    pub liaison_cutoff: Option<f64>,
+}
+
+pub fn get_event_stages(db: &Connection, event_id: i64) -> Vec<EventStage> {
+    let rows = db
+        .query(
+            "SELECT event_segments.stage_index, segments.id,
+                COALESCE(event_segments.stage_name, segments.name) AS name,
+                event_segments.liaison_cutoff
+            FROM segments
+            INNER JOIN event_segments ON event_segments.segment_id = segments.id
+            WHERE event_segments.event_id = $1
+            ORDER BY event_segments.stage_index",
+            &[&event_id],
+        )
+        .unwrap();
+
+    rows.iter()
+        .map(|row| EventStage {
+            index: row.get("stage_index"),
+            segment_id: row.get("id"),
+            name: row.get("name"),
+            liaison_cutoff: row.get("liaison_cutoff"),
+        })
+        .collect()
+}
+
+fn get_stage_results(
+    db: &Connection,
+    stages: &[EventStage],
+    participation_id: i64,
+) -> Vec<StageResult> {
+    let split_rows = db
+        .query(
+            "SELECT * FROM participation_segments WHERE participation_id = $1",
+            &[&participation_id],
+        )
+        .unwrap();
+
+    stages
+        .iter()
+        .map(|stage| {
+            let segment_id = stage.segment_id;
+            let name = stage.name.clone();
+            let split = split_rows.iter().find(|row| {
+                let split_segment_id: i64 = row.get("segment_id");
+                split_segment_id == segment_id
+            });
+
+            match split {
+                Some(row) => StageResult {
+                    segment_id,
+                    name,
+                    elapsed: row.get("elapsed_seconds"),
+                    started_at: row.get("started_at"),
+                    finished_at: row.get("finished_at"),
+                    sensors: row_to_sensor_summary(&row),
+                    speed: row_to_speed_stats(&row),
+                    sectors: row.get("sector_seconds"),
+                },
+                None => StageResult {
+                    segment_id,
+                    name,
+                    elapsed: None,
+                    started_at: None,
+                    finished_at: None,
+                    sensors: SensorSummary::default(),
+                    speed: None,
+                    sectors: None,
+                },
+            }
+        })
+        .collect()
+}
+
+pub fn get_event_results(db: &Connection, event_id: i64) -> Vec<EventResult> {
+    let event_rows = db.query(
+        "SELECT participations.id AS participation_id, event_riders.category, * FROM participations INNER JOIN users ON participations.event_id = $1 AND participations.counted AND users.id = participations.user_id LEFT JOIN event_riders ON event_riders.event_id = participations.event_id AND event_riders.user_id = users.id ORDER BY participations.total_elapsed_seconds ASC",
+        &[&event_id],
+    ).unwrap();
+
+    let event_stages = get_event_stages(db, event_id);
+
+    event_rows
+        .iter()
+        .map(|row| {
+            let participation_id: i64 = row.get("participation_id");
+            let username: String = row.get("name");
+            let category: Option<String> = row.get("category");
+            let maybe_elapsed: Option<postgres::Result<f64>> = row.get_opt("total_elapsed_seconds");
+            let time = match maybe_elapsed {
+                Some(Ok(elapsed)) => elapsed,
+                Some(Err(..)) | None => 0.0,
+            };
+            let start_time: Option<DateTime<Utc>> = row.get("start_time");
+            let upload_id: Option<i64> = row.get("upload_id");
+            let penalty: Option<f64> = row.get("penalty_seconds");
+            let stages = get_stage_results(db, &event_stages, participation_id);
+
+            EventResult {
+                participation_id,
+                username,
+                category,
+                time,
+                penalty,
+                start_time,
+                upload_id,
+                stages,
+            }
+        })
+        .collect()
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EventDetails {
+    pub id: i64,
     pub name: String,
+    pub date: Option<NaiveDate>,
+    pub start_window_open: Option<DateTime<Utc>>,
+    pub start_window_close: Option<DateTime<Utc>>,
+    /// Whether stages have to be ridden in order to be timed
+    pub stages_in_order: bool,
+    pub duplicate_policy: DuplicatePolicy,
+    pub timing: EventTiming,
+    pub stages: Vec<EventStage>,
     pub results: Vec<EventResult>,
+    /// The fastest counted time through every sector of the stages
+    pub sector_leaders: Vec<SectorLeader>,
+}
+
+/// Find the fastest rider through every sector of every stage in a list of results.
+fn get_sector_leaders(results: &[EventResult]) -> Vec<SectorLeader> {
+    let mut leaders: Vec<SectorLeader> = Vec::new();
+    for result in results {
+        for stage in &result.stages {
+            let sectors = match stage.sectors {
+                Some(ref sectors) => sectors,
+                None => continue,
+            };
+            for (sector, &elapsed) in sectors.iter().enumerate() {
+                let leader = leaders
+                    .iter_mut()
+                    .find(|l| l.segment_id == stage.segment_id && l.sector == sector);
+                match leader {
+                    Some(ref leader) if leader.elapsed <= elapsed => (),
+                    Some(leader) => {
+                        leader.participation_id = result.participation_id;
+                        leader.username = result.username.clone();
+                        leader.elapsed = elapsed;
+                    }
+                    None => leaders.push(SectorLeader {
+                        segment_id: stage.segment_id,
+                        sector,
+                        participation_id: result.participation_id,
+                        username: result.username.clone(),
+                        elapsed,
+                    }),
+                }
+            }
+        }
+    }
+
+    leaders
 }
 
 pub fn get_event(db: &Connection, event_id: i64) -> Option<EventDetails> {
     match db.query("SELECT * FROM events WHERE id = $1", &[&event_id]) {
-        Ok(rows) => Some(EventDetails {
-            name: rows.get(0).get("name"),
-            results: get_event_results(db, event_id),
-        }),
-        Err(..) => None,
+        Ok(ref rows) if !rows.is_empty() => {
+            let results = get_event_results(db, event_id);
+            Some(EventDetails {
+                id: event_id,
+                name: rows.get(0).get("name"),
+                date: rows.get(0).get("date"),
+                start_window_open: rows.get(0).get("start_window_open"),
+                start_window_close: rows.get(0).get("start_window_close"),
+                stages_in_order: rows.get(0).get("stages_in_order"),
+                duplicate_policy: DuplicatePolicy::from_name(
+                    &rows.get(0).get::<_, String>("duplicate_policy"),
+                )
+                .unwrap_or_default(),
+                timing: event_timing_from_row(&rows.get(0)),
+                stages: get_event_stages(db, event_id),
+                sector_leaders: get_sector_leaders(&results),
+                results,
+            })
+        }
+        _ => None,
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EventInfo {
+    pub id: i64,
     pub name: String,
 }
 
 pub fn get_events(conn: &Connection) -> Option<Vec<EventInfo>> {
-    match conn.query("SELECT name FROM events", &[]) {
+    match conn.query("SELECT id, name FROM events", &[]) {
         Ok(rows) => Some(
             rows.iter()
                 .map(|row| {
+                    let id: i64 = row.get("id");
                     let name: String = row.get("name");
-                    EventInfo { name }
+                    EventInfo { id, name }
                 })
                 .collect(),
         ),
         Err(..) => None,
     }
 }
+
+/// Events using a segment as one of their stages.
+pub fn get_segment_events(db: &Connection, segment_id: i64) -> Vec<EventInfo> {
+    db.query(
+        "SELECT events.id, events.name FROM events
+        INNER JOIN event_segments ON event_segments.event_id = events.id
+        WHERE event_segments.segment_id = $1
+        ORDER BY events.date, events.id",
+        &[&segment_id],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| EventInfo {
+        id: row.get("id"),
+        name: row.get("name"),
+    })
+    .collect()
+}
+
+/// A timed ride of a segment, in any event using it or in an activity.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SegmentEffort {
+    pub participation_id: i64,
+    /// Unset for efforts in activities
+    pub event_id: Option<i64>,
+    pub event_name: Option<String>,
+    pub user_id: i64,
+    pub username: String,
+    pub elapsed: f64,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+const SEGMENT_EFFORT_COLUMNS: &'static str = "participation_segments.participation_id,
+    participations.event_id,
+    events.name AS event_name,
+    participations.user_id,
+    users.name AS username,
+    participation_segments.elapsed_seconds,
+    participation_segments.started_at,
+    participation_segments.finished_at";
+
+const SEGMENT_EFFORT_TABLES: &'static str = "FROM participation_segments
+    INNER JOIN participations ON participations.id = participation_segments.participation_id
+    LEFT JOIN events ON events.id = participations.event_id
+    INNER JOIN users ON users.id = participations.user_id
+    WHERE participation_segments.segment_id = $1 AND participation_segments.elapsed_seconds IS NOT NULL
+        AND participations.counted";
+
+fn segment_effort_from_row(row: &postgres::rows::Row) -> SegmentEffort {
+    SegmentEffort {
+        participation_id: row.get("participation_id"),
+        event_id: row.get("event_id"),
+        event_name: row.get("event_name"),
+        user_id: row.get("user_id"),
+        username: row.get("username"),
+        elapsed: row.get("elapsed_seconds"),
+        started_at: row.get("started_at"),
+        finished_at: row.get("finished_at"),
+    }
+}
+
+/// All timed rides of a segment across events, fastest first.
+pub fn get_segment_efforts(db: &Connection, segment_id: i64) -> Vec<SegmentEffort> {
+    db.query(
+        &format!(
+            "SELECT {} {}
+            ORDER BY participation_segments.elapsed_seconds, participation_segments.started_at",
+            SEGMENT_EFFORT_COLUMNS, SEGMENT_EFFORT_TABLES
+        ),
+        &[&segment_id],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| segment_effort_from_row(&row))
+    .collect()
+}
+
+/// The personal best of every rider on a segment, fastest first. Without a limit all riders are
+/// listed.
+pub fn get_segment_leaderboard(
+    db: &Connection,
+    segment_id: i64,
+    limit: Option<i64>,
+) -> Vec<SegmentEffort> {
+    db.query(
+        &format!(
+            "SELECT * FROM (
+                SELECT DISTINCT ON (participations.user_id) {} {}
+                ORDER BY participations.user_id, participation_segments.elapsed_seconds, participation_segments.started_at
+            ) AS personal_bests
+            ORDER BY elapsed_seconds, started_at
+            LIMIT $2",
+            SEGMENT_EFFORT_COLUMNS, SEGMENT_EFFORT_TABLES
+        ),
+        &[&segment_id, &limit],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| segment_effort_from_row(&row))
+    .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SegmentDetails {
+    pub segment: Segment,
+    pub events: Vec<EventInfo>,
+    pub leaderboard: Vec<SegmentEffort>,
+    pub sectors: Vec<SegmentSector>,
+    pub sector_leaders: Vec<SectorLeader>,
+}
+
+/// Get the split points of a segment in order along it.
+pub fn get_segment_sectors(db: &Connection, segment_id: i64) -> Vec<SegmentSector> {
+    db.query(
+        "SELECT id, distance, name, ST_Y(geom::geometry) AS lat, ST_X(geom::geometry) AS lon
+        FROM segment_sectors WHERE segment_id = $1 ORDER BY distance",
+        &[&segment_id],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| SegmentSector {
+        id: row.get("id"),
+        distance: row.get("distance"),
+        name: row.get("name"),
+        lat: row.get("lat"),
+        lon: row.get("lon"),
+    })
+    .collect()
+}
+
+/// Split a segment at a point along it and retime everything using the segment. Distances
+/// beyond either end of the segment are clamped to it, points given by position are moved onto
+/// the segment line.
+pub fn add_segment_sector(
+    db: &Connection,
+    segment_id: i64,
+    name: Option<&str>,
+    position: SectorPosition,
+) -> Result<i64, Error> {
+    let trans = db.transaction()?;
+
+    let rows = match position {
+        SectorPosition::Distance(distance) => trans.query(
+            "INSERT INTO segment_sectors (segment_id, distance, name, geom)
+            SELECT id, location * ST_Length(geom), $3, ST_LineInterpolatePoint(geom::geometry, location)::geography
+            FROM (
+                SELECT id, geom, LEAST(GREATEST($2 / ST_Length(geom), 0), 1) AS location
+                FROM segments WHERE id = $1
+            ) AS segment
+            RETURNING id",
+            &[&segment_id, &distance, &name],
+        )?,
+        SectorPosition::Point { lat, lon } => trans.query(
+            "INSERT INTO segment_sectors (segment_id, distance, name, geom)
+            SELECT id, location * ST_Length(geom), $4, ST_LineInterpolatePoint(geom::geometry, location)::geography
+            FROM (
+                SELECT id, geom, ST_LineLocatePoint(geom::geometry, ST_SetSRID(ST_MakePoint($2, $3), 4326)) AS location
+                FROM segments WHERE id = $1
+            ) AS segment
+            RETURNING id",
+            &[&segment_id, &lon, &lat, &name],
+        )?,
+    };
+    if rows.is_empty() {
+        return Err(Error::NotFound(format!("segment {}", segment_id)));
+    }
+    let sector_id: i64 = rows.get(0).get(0);
+
+    retime_segment(&trans, segment_id)?;
+    trans.commit()?;
+
+    Ok(sector_id)
+}
+
+/// Remove a split point and retime everything using its segment. Returns false if there is no
+/// such split point.
+pub fn delete_segment_sector(db: &Connection, sector_id: i64) -> Result<bool, postgres::Error> {
+    let trans = db.transaction()?;
+
+    let rows = trans.query(
+        "DELETE FROM segment_sectors WHERE id = $1 RETURNING segment_id",
+        &[&sector_id],
+    )?;
+    if rows.is_empty() {
+        return Ok(false);
+    }
+
+    retime_segment(&trans, rows.get(0).get("segment_id"))?;
+    trans.commit()?;
+
+    Ok(true)
+}
+
+/// Find the fastest counted time through every sector of a segment.
+pub fn get_segment_sector_leaders(db: &Connection, segment_id: i64) -> Vec<SectorLeader> {
+    db.query(
+        "SELECT DISTINCT ON (sector.index) sector.index, sector.seconds, participations.id AS participation_id, users.name
+        FROM participation_segments
+        INNER JOIN participations ON participations.id = participation_segments.participation_id
+        INNER JOIN users ON users.id = participations.user_id
+        CROSS JOIN LATERAL unnest(participation_segments.sector_seconds) WITH ORDINALITY AS sector(seconds, index)
+        WHERE participation_segments.segment_id = $1 AND participations.counted
+        ORDER BY sector.index, sector.seconds",
+        &[&segment_id],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| {
+        let index: i64 = row.get("index");
+        SectorLeader {
+            segment_id,
+            sector: index as usize - 1,
+            participation_id: row.get("participation_id"),
+            username: row.get("name"),
+            elapsed: row.get("seconds"),
+        }
+    })
+    .collect()
+}
+
+/// Number of riders in the leaderboard of a segment page.
+pub const LEADERBOARD_SIZE: i64 = 10;
+
+pub fn get_segment_details(db: &Connection, segment_id: i64) -> Option<SegmentDetails> {
+    get_segment(db, segment_id).map(|segment| SegmentDetails {
+        segment,
+        events: get_segment_events(db, segment_id),
+        leaderboard: get_segment_leaderboard(db, segment_id, Some(LEADERBOARD_SIZE)),
+        sectors: get_segment_sectors(db, segment_id),
+        sector_leaders: get_segment_sector_leaders(db, segment_id),
+    })
+}
+
+/// A ride uploaded outside of any event with the segments it was timed on.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Activity {
+    pub id: i64,
+    pub user_id: i64,
+    pub start_time: Option<DateTime<Utc>>,
+    /// The file the activity was created from
+    pub upload_id: Option<i64>,
+    pub efforts: Vec<StageResult>,
+}
+
+fn get_activity_efforts(db: &Connection, participation_id: i64) -> Vec<StageResult> {
+    db.query(
+        "SELECT participation_segments.*, segments.name FROM participation_segments
+        INNER JOIN segments ON segments.id = participation_segments.segment_id
+        WHERE participation_segments.participation_id = $1
+        ORDER BY participation_segments.started_at",
+        &[&participation_id],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| StageResult {
+        segment_id: row.get("segment_id"),
+        name: row.get("name"),
+        elapsed: row.get("elapsed_seconds"),
+        started_at: row.get("started_at"),
+        finished_at: row.get("finished_at"),
+        sensors: row_to_sensor_summary(&row),
+        speed: row_to_speed_stats(&row),
+        sectors: row.get("sector_seconds"),
+    })
+    .collect()
+}
+
+pub fn get_activity(db: &Connection, activity_id: i64) -> Option<Activity> {
+    let rows = db
+        .query(
+            "SELECT id, user_id, start_time, upload_id FROM participations WHERE id = $1 AND event_id IS NULL",
+            &[&activity_id],
+        )
+        .unwrap();
+    if rows.is_empty() {
+        return None;
+    }
+
+    let row = rows.get(0);
+    Some(Activity {
+        id: activity_id,
+        user_id: row.get("user_id"),
+        start_time: row.get("start_time"),
+        upload_id: row.get("upload_id"),
+        efforts: get_activity_efforts(db, activity_id),
+    })
+}
+
+/// All activities of a user, latest first.
+pub fn get_user_activities(db: &Connection, user_id: i64) -> Vec<Activity> {
+    db.query(
+        "SELECT id, user_id, start_time, upload_id FROM participations
+        WHERE user_id = $1 AND event_id IS NULL
+        ORDER BY start_time DESC",
+        &[&user_id],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| {
+        let id: i64 = row.get("id");
+        Activity {
+            id,
+            user_id: row.get("user_id"),
+            start_time: row.get("start_time"),
+            upload_id: row.get("upload_id"),
+            efforts: get_activity_efforts(db, id),
+        }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lon: f64, lat: f64) -> ewkb::Point {
+        ewkb::Point {
+            x: lon,
+            y: lat,
+            srid: Some(4326),
+        }
+    }
+
+    fn point_z(lon: f64, lat: f64, z: f64) -> ewkb::PointZ {
+        ewkb::PointZ {
+            x: lon,
+            y: lat,
+            z,
+            srid: Some(4326),
+        }
+    }
+
+    /// A part of a ride heading north along a meridian, given the latitude and seconds at every
+    /// point.
+    fn line(points: &[(f64, f64)]) -> ewkb::LineStringZ {
+        ewkb::LineStringZ {
+            points: points
+                .iter()
+                .map(|&(lat, z)| point_z(17.0, lat, z))
+                .collect(),
+            srid: Some(4326),
+        }
+    }
+
+    fn elapsed(matches: &[SegmentMatch]) -> Vec<f64> {
+        matches.iter().map(|m| m.elapsed).collect()
+    }
+
+    #[test]
+    fn every_attempt_is_found() {
+        let lines = [
+            line(&[(62.5, 0.0), (62.502, 20.0), (62.503, 30.0)]),
+            line(&[(62.5, 100.0), (62.502, 130.0), (62.503, 140.0)]),
+        ];
+        let matches = match_segments(&lines, &point(17.0, 62.5), &point(17.0, 62.503), &[], 20.0);
+        assert_eq!(elapsed(&matches), vec![30.0, 40.0]);
+        assert_eq!(matches[1].start, 100.0);
+        assert_eq!(matches[1].finish, 140.0);
+    }
+
+    #[test]
+    fn connected_parts_are_chained_without_the_gap() {
+        let lines = [
+            line(&[(62.5, 0.0), (62.5015, 15.0)]),
+            line(&[(62.5015, 20.0), (62.503, 35.0)]),
+        ];
+        let matches = match_segments(&lines, &point(17.0, 62.5), &point(17.0, 62.503), &[], 20.0);
+        assert_eq!(elapsed(&matches), vec![30.0]);
+        assert_eq!(matches[0].start, 0.0);
+        assert_eq!(matches[0].finish, 35.0);
+        assert_eq!(matches[0].geom.points.len(), 4);
+    }
+
+    #[test]
+    fn speed_is_computed_without_the_gap() {
+        // Riding steadily on both parts, with a minute between them
+        let lines = [
+            line(&[(62.5, 0.0), (62.5015, 15.0)]),
+            line(&[(62.5015, 75.0), (62.503, 90.0)]),
+        ];
+        let matches = match_segments(&lines, &point(17.0, 62.5), &point(17.0, 62.503), &[], 20.0);
+        let continuous = &matches[0].continuous;
+        let seconds: Vec<f64> = continuous.points.iter().map(|p| p.z).collect();
+        assert_eq!(seconds, vec![0.0, 15.0, 15.0, 30.0]);
+
+        let stats = speed_stats(continuous);
+        assert_eq!(stats.stopped_seconds, 0.0);
+        assert_eq!(stats.moving_seconds, 30.0);
+        assert!((stats.avg_speed - stats.distance / 30.0).abs() < 1e-9);
+
+        let series = distance_time_series(continuous);
+        assert_eq!(series.last().unwrap().seconds, 30.0);
+    }
+
+    #[test]
+    fn incomplete_attempts_are_skipped() {
+        let lines = [
+            line(&[(62.5, 0.0), (62.501, 10.0)]),
+            line(&[(62.5, 50.0), (62.503, 80.0)]),
+        ];
+        let matches = match_segments(&lines, &point(17.0, 62.5), &point(17.0, 62.503), &[], 20.0);
+        assert_eq!(elapsed(&matches), vec![30.0]);
+        assert_eq!(matches[0].start, 50.0);
+    }
+
+    #[test]
+    fn identical_points_are_close() {
+        assert!(check_dist(
+            &point(17.0, 62.5),
+            &point_z(17.0, 62.5, 0.0),
+            20.0
+        ));
+    }
+
+    #[test]
+    fn distant_points_are_not_close() {
+        // The equality shortcut once compared the first point with itself, so every point
+        // matched regardless of distance
+        assert!(!check_dist(
+            &point(17.0, 62.5),
+            &point_z(17.0, 62.51, 0.0),
+            20.0
+        ));
+        assert!(!check_dist(
+            &point(17.0, 62.5),
+            &point_z(17.1, 62.5, 0.0),
+            20.0
+        ));
+    }
+
+    #[test]
+    fn points_within_the_threshold_are_close() {
+        // About 11 meters apart
+        assert!(check_dist(
+            &point(17.0, 62.5),
+            &point_z(17.0, 62.5001, 0.0),
+            20.0
+        ));
+        assert!(!check_dist(
+            &point(17.0, 62.5),
+            &point_z(17.0, 62.5001, 0.0),
+            5.0
+        ));
+    }
+}
@@ -0,0 +1,368 @@
+//! Event manifests describe an event in a single TOML or JSON file: its stages in order with the
+//! files holding their lines, categories and riders with their tracks. File paths are relative to
+//! the manifest.
+
+use chrono::prelude::*;
+use postgres::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use duplicate::DuplicatePolicy;
+use error::Error;
+use export::tracks_to_gpx;
+use formats::{detect_format, read_track};
+use simplify::SimplifyOptions;
+use timing::{EventTiming, TimingMode, DEFAULT_LATE_PENALTY};
+use track::NamedTrack;
+use validation::ValidationReport;
+
+use super::{
+    add_event_category, create_event, create_participation, create_segment, create_user, get_event,
+    get_event_categories, get_event_riders, get_matched_tracks, get_segment, get_segment_track,
+    get_upload_data_for, get_user_by_email, read_whole_file, read_whole_file_bytes, register_rider,
+    set_duplicate_policy, set_event_timing, set_event_window, set_liaison_cutoff, set_stage_name,
+    set_stages_in_order, EventWindow, UploadFile, DEFAULT_TOLERANCE,
+};
+
+fn default_tolerance() -> f64 {
+    DEFAULT_TOLERANCE
+}
+
+fn default_late_penalty() -> f64 {
+    DEFAULT_LATE_PENALTY
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StageManifest {
+    /// Name of the segment
+    pub name: String,
+    /// Name of the stage in this event, if it differs from the segment name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage_name: Option<String>,
+    pub file: String,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// Seconds allowed to reach the start of the stage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liaison_cutoff: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RiderManifest {
+    pub name: String,
+    pub email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Track of the rider, riders without one are only registered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventManifest {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<NaiveDate>,
+    /// Stages only count when ridden in the order they are listed
+    #[serde(default)]
+    pub stages_in_order: bool,
+    /// Which participation counts when a rider uploads more than once
+    #[serde(default)]
+    pub duplicate_policy: DuplicatePolicy,
+    /// How the total time is computed
+    #[serde(default)]
+    pub timing_mode: TimingMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mass_start: Option<DateTime<Utc>>,
+    /// Seconds added per started minute late at a stage
+    #[serde(default = "default_late_penalty")]
+    pub late_penalty: f64,
+    /// Only attempts started from this time on count
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_window_open: Option<DateTime<Utc>>,
+    /// Only attempts started up to this time count
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_window_close: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    pub stages: Vec<StageManifest>,
+    #[serde(default)]
+    pub riders: Vec<RiderManifest>,
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "json")
+}
+
+/// Read a manifest, JSON if the file name ends in `.json` and TOML otherwise.
+pub fn read_manifest(path: &Path) -> Result<EventManifest, Error> {
+    let contents = read_whole_file(&path.to_string_lossy())?;
+    let manifest: EventManifest = if is_json(path) {
+        serde_json::from_str(&contents).map_err(|err| Error::Parse(err.to_string()))?
+    } else {
+        toml::from_str(&contents).map_err(|err| Error::Parse(err.to_string()))?
+    };
+
+    for rider in &manifest.riders {
+        if let Some(ref category) = rider.category {
+            if !manifest.categories.contains(category) {
+                return Err(Error::Parse(format!(
+                    "rider {} is in unknown category {}",
+                    rider.name, category
+                )));
+            }
+        }
+    }
+
+    Ok(manifest)
+}
+
+pub fn write_manifest(path: &Path, manifest: &EventManifest) -> Result<(), Error> {
+    let contents = if is_json(path) {
+        serde_json::to_string_pretty(manifest).map_err(|err| Error::Parse(err.to_string()))?
+    } else {
+        toml::to_string(manifest).map_err(|err| Error::Parse(err.to_string()))?
+    };
+
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ImportSummary {
+    pub event_id: i64,
+    /// Riders whose tracks did not pass validation, they are registered without a result
+    pub rejected: Vec<(String, ValidationReport)>,
+}
+
+/// Create an event with all its stages, categories and riders from a manifest. The import runs
+/// in one transaction, nothing is stored if any part of it fails.
+pub fn import_manifest(db: &Connection, path: &Path) -> Result<ImportSummary, Error> {
+    let manifest = read_manifest(path)?;
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let trans = db.transaction()?;
+
+    let mut segment_ids = Vec::new();
+    for stage in &manifest.stages {
+        let data = read_whole_file_bytes(&base.join(&stage.file).to_string_lossy())?;
+        let track = read_track(&data)?;
+        let upload = UploadFile {
+            data: &data,
+            filename: Some(&stage.file),
+            uploader_id: None,
+        };
+        segment_ids.push(create_segment(
+            &trans,
+            &stage.name,
+            stage.tolerance,
+            &track.points,
+            Some(&upload),
+        )?);
+    }
+
+    let event_id = create_event(&trans, &manifest.name, manifest.date, &segment_ids)?;
+    if manifest.stages_in_order {
+        set_stages_in_order(&trans, event_id, true)?;
+    }
+    set_duplicate_policy(&trans, event_id, manifest.duplicate_policy)?;
+    let timing = EventTiming {
+        mode: manifest.timing_mode,
+        mass_start: manifest.mass_start,
+        late_penalty: manifest.late_penalty,
+    };
+    set_event_timing(&trans, event_id, &timing)?;
+    if manifest.start_window_open.is_some() || manifest.start_window_close.is_some() {
+        let window = EventWindow {
+            open: manifest.start_window_open,
+            close: manifest.start_window_close,
+        };
+        set_event_window(&trans, event_id, &window)?;
+    }
+    for (stage, &segment_id) in manifest.stages.iter().zip(&segment_ids) {
+        if let Some(ref stage_name) = stage.stage_name {
+            set_stage_name(&trans, event_id, segment_id, Some(stage_name))?;
+        }
+        if stage.liaison_cutoff.is_some() {
+            set_liaison_cutoff(&trans, event_id, segment_id, stage.liaison_cutoff)?;
+        }
+    }
+    for category in &manifest.categories {
+        add_event_category(&trans, event_id, category)?;
+    }
+
+    let mut rejected = Vec::new();
+    for rider in &manifest.riders {
+        let user = match get_user_by_email(&trans, &rider.email) {
+            Some(user) => user,
+            None => create_user(&trans, &rider.name, &rider.email)
+                .ok_or_else(|| Error::Parse(format!("could not create user {}", rider.name)))?,
+        };
+        register_rider(
+            &trans,
+            event_id,
+            user.id,
+            rider.category.as_ref().map(|c| c.as_str()),
+        )?;
+
+        if let Some(ref file) = rider.file {
+            let data = read_whole_file_bytes(&base.join(file).to_string_lossy())?;
+            let track = read_track(&data)?;
+            let upload = UploadFile {
+                data: &data,
+                filename: Some(file),
+                uploader_id: Some(user.id),
+            };
+            let options = SimplifyOptions::default();
+            match create_participation(
+                &trans,
+                event_id,
+                user.id,
+                &track.points,
+                &options,
+                Some(&upload),
+            ) {
+                Ok(_) => (),
+                Err(Error::Invalid(report)) => rejected.push((rider.name.clone(), report)),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    trans.commit()?;
+
+    Ok(ImportSummary { event_id, rejected })
+}
+
+/// A file name in `dir` for a track, made from a name that is not used by any other file of the
+/// export.
+fn file_name(used: &mut HashSet<String>, dir: &str, name: &str, extension: &str) -> String {
+    let name = name.replace(|c: char| c == '/' || c == '\\', "_");
+    let mut file = format!("{}/{}.{}", dir, name, extension);
+    let mut count = 1;
+    while used.contains(&file) {
+        count += 1;
+        file = format!("{}/{} {}.{}", dir, name, count, extension);
+    }
+    used.insert(file.clone());
+    file
+}
+
+/// Write the manifest of an event, with the stage lines in `stages/` and rider tracks in
+/// `riders/` next to it, so it can be imported again.
+pub fn export_manifest(db: &Connection, event_id: i64, path: &Path) -> Result<(), Error> {
+    let event =
+        get_event(db, event_id).ok_or_else(|| Error::NotFound(format!("event {}", event_id)))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(base.join("stages"))?;
+    fs::create_dir_all(base.join("riders"))?;
+
+    let mut used = HashSet::new();
+    let mut stages = Vec::new();
+    for stage in &event.stages {
+        let not_found = || Error::NotFound(format!("segment {}", stage.segment_id));
+        let segment = get_segment(db, stage.segment_id).ok_or_else(not_found)?;
+        let points = get_segment_track(db, stage.segment_id).ok_or_else(not_found)?;
+        let file = file_name(&mut used, "stages", &segment.name, "gpx");
+        fs::write(
+            base.join(&file),
+            tracks_to_gpx(&[NamedTrack {
+                name: segment.name.clone(),
+                points,
+            }]),
+        )?;
+
+        let stage_name = if stage.name != segment.name {
+            Some(stage.name.clone())
+        } else {
+            None
+        };
+        stages.push(StageManifest {
+            name: segment.name,
+            stage_name,
+            file,
+            tolerance: segment.tolerance,
+            liaison_cutoff: stage.liaison_cutoff,
+        });
+    }
+
+    let mut riders = Vec::new();
+    for rider in get_event_riders(db, event_id) {
+        let mut file = None;
+        if let Some(participation_id) = rider.participation_id {
+            // The uploaded file keeps everything that was recorded, participations without one
+            // are written from their stored track
+            let rider_file = match get_upload_data_for(db, "participations", participation_id)? {
+                Some(data) => {
+                    let extension = detect_format(&data).map_or("gpx", |format| format.extension());
+                    let rider_file = file_name(&mut used, "riders", &rider.user.name, extension);
+                    fs::write(base.join(&rider_file), data)?;
+                    rider_file
+                }
+                None => {
+                    // The first track is the full ride, the rest are the matched stages
+                    let mut tracks = get_matched_tracks(db, participation_id).ok_or_else(|| {
+                        Error::NotFound(format!("participation {}", participation_id))
+                    })?;
+                    tracks.truncate(1);
+                    let rider_file = file_name(&mut used, "riders", &rider.user.name, "gpx");
+                    fs::write(base.join(&rider_file), tracks_to_gpx(&tracks))?;
+                    rider_file
+                }
+            };
+            file = Some(rider_file);
+        }
+
+        riders.push(RiderManifest {
+            name: rider.user.name,
+            email: rider.user.email,
+            category: rider.category,
+            file,
+        });
+    }
+
+    let manifest = EventManifest {
+        name: event.name,
+        date: event.date,
+        stages_in_order: event.stages_in_order,
+        duplicate_policy: event.duplicate_policy,
+        timing_mode: event.timing.mode,
+        mass_start: event.timing.mass_start,
+        late_penalty: event.timing.late_penalty,
+        start_window_open: event.start_window_open,
+        start_window_close: event.start_window_close,
+        categories: get_event_categories(db, event_id),
+        stages,
+        riders,
+    };
+
+    write_manifest(path, &manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_are_unique() {
+        let mut used = HashSet::new();
+        assert_eq!(
+            file_name(&mut used, "riders", "John Doe", "gpx"),
+            "riders/John Doe.gpx"
+        );
+        assert_eq!(
+            file_name(&mut used, "riders", "John Doe", "gpx"),
+            "riders/John Doe 2.gpx"
+        );
+        assert_eq!(
+            file_name(&mut used, "riders", "John Doe", "fit"),
+            "riders/John Doe.fit"
+        );
+        assert_eq!(
+            file_name(&mut used, "stages", "A/B", "gpx"),
+            "stages/A_B.gpx"
+        );
+    }
+}
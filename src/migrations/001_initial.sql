@@ -15,7 +15,10 @@ CREATE TABLE segments (
 
 CREATE TABLE events (
 	id BIGSERIAL PRIMARY KEY,
-	name VARCHAR NOT NULL
+	name VARCHAR NOT NULL,
+	date DATE DEFAULT NULL,
+	start_window_open TIMESTAMPTZ DEFAULT NULL,
+	start_window_close TIMESTAMPTZ DEFAULT NULL
 );
 
 CREATE TABLE event_segments (
@@ -29,11 +32,15 @@ CREATE TABLE participations (
 	event_id BIGINT REFERENCES events(id),
 	user_id BIGINT REFERENCES users(id),
 	total_elapsed_seconds DOUBLE PRECISION DEFAULT NULL,
+	start_time TIMESTAMPTZ DEFAULT NULL,
 	geom GEOGRAPHY(LINESTRINGZ,4326) DEFAULT NULL
 );
 
 CREATE TABLE participation_segments (
 	participation_id BIGINT REFERENCES participations(id) ON UPDATE CASCADE ON DELETE CASCADE,
 	segment_id BIGINT REFERENCES segments(id) ON UPDATE CASCADE,
-	elapsed_seconds DOUBLE PRECISION DEFAULT NULL
+	elapsed_seconds DOUBLE PRECISION DEFAULT NULL,
+	started_at TIMESTAMPTZ DEFAULT NULL,
+	finished_at TIMESTAMPTZ DEFAULT NULL,
+	geom GEOGRAPHY(LINESTRINGZ,4326) DEFAULT NULL
 );
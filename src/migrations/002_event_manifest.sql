@@ -0,0 +1,14 @@
+ALTER TABLE segments ADD COLUMN tolerance DOUBLE PRECISION NOT NULL DEFAULT 20;
+
+CREATE TABLE event_categories (
+	event_id BIGINT REFERENCES events(id) ON UPDATE CASCADE ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	CONSTRAINT event_categories_pkey PRIMARY KEY (event_id, name)
+);
+
+CREATE TABLE event_riders (
+	event_id BIGINT REFERENCES events(id) ON UPDATE CASCADE ON DELETE CASCADE,
+	user_id BIGINT REFERENCES users(id) ON UPDATE CASCADE,
+	category VARCHAR DEFAULT NULL,
+	CONSTRAINT event_riders_pkey PRIMARY KEY (event_id, user_id)
+);
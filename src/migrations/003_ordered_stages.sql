@@ -0,0 +1,15 @@
+ALTER TABLE event_segments ADD COLUMN stage_index INTEGER;
+ALTER TABLE event_segments ADD COLUMN stage_name VARCHAR DEFAULT NULL;
+
+-- Number the stages of existing events in the order they used to be listed in
+UPDATE event_segments SET stage_index = numbered.stage_index
+FROM (
+	SELECT event_id, segment_id, ROW_NUMBER() OVER (PARTITION BY event_id ORDER BY segment_id) - 1 AS stage_index
+	FROM event_segments
+) AS numbered
+WHERE event_segments.event_id = numbered.event_id AND event_segments.segment_id = numbered.segment_id;
+
+ALTER TABLE event_segments ALTER COLUMN stage_index SET NOT NULL;
+ALTER TABLE event_segments ADD CONSTRAINT event_segments_stage_index_key UNIQUE (event_id, stage_index);
+
+ALTER TABLE events ADD COLUMN stages_in_order BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Activities are participations without an event, matched against every segment they pass
+CREATE INDEX segments_geom_idx ON segments USING GIST (geom);
+CREATE INDEX participations_user_id_idx ON participations (user_id);
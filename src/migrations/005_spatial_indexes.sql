@@ -0,0 +1,6 @@
+CREATE INDEX segments_geom_expanded_idx ON segments USING GIST (geom_expanded);
+CREATE INDEX participations_geom_idx ON participations USING GIST (geom);
+CREATE INDEX participations_event_id_idx ON participations (event_id);
+CREATE INDEX participation_segments_participation_id_idx ON participation_segments (participation_id);
+CREATE INDEX participation_segments_segment_id_idx ON participation_segments (segment_id);
+CREATE INDEX event_segments_segment_id_idx ON event_segments (segment_id);
@@ -0,0 +1,3 @@
+-- The track as uploaded, geom holds the simplified track used for matching
+ALTER TABLE participations ADD COLUMN raw_geom GEOGRAPHY(LINESTRINGZ,4326) DEFAULT NULL;
+UPDATE participations SET raw_geom = geom;
@@ -0,0 +1,14 @@
+-- Uploaded files as they were received, kept for audits and to rebuild everything derived from them
+CREATE TABLE uploads (
+	id BIGSERIAL PRIMARY KEY,
+	sha256 VARCHAR(64) NOT NULL,
+	filename VARCHAR DEFAULT NULL,
+	uploaded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	uploader_id BIGINT REFERENCES users(id) DEFAULT NULL,
+	data BYTEA NOT NULL
+);
+
+CREATE INDEX uploads_sha256_idx ON uploads (sha256);
+
+ALTER TABLE participations ADD COLUMN upload_id BIGINT REFERENCES uploads(id) DEFAULT NULL;
+ALTER TABLE segments ADD COLUMN upload_id BIGINT REFERENCES uploads(id) DEFAULT NULL;
@@ -0,0 +1,11 @@
+ALTER TABLE events ADD COLUMN duplicate_policy VARCHAR NOT NULL DEFAULT 'replace';
+ALTER TABLE participations ADD COLUMN counted BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Only the latest participation of a rider in an event counts
+UPDATE participations SET counted = FALSE
+WHERE event_id IS NOT NULL AND id NOT IN (
+	SELECT MAX(id) FROM participations WHERE event_id IS NOT NULL GROUP BY event_id, user_id
+);
+
+CREATE UNIQUE INDEX participations_counted_key ON participations (event_id, user_id)
+WHERE counted AND event_id IS NOT NULL;
@@ -0,0 +1,13 @@
+-- Sensor readings of a participation, one entry per point of the raw track
+CREATE TABLE participation_sensors (
+	participation_id BIGINT PRIMARY KEY REFERENCES participations(id) ON DELETE CASCADE,
+	seconds DOUBLE PRECISION[] NOT NULL,
+	heart_rate SMALLINT[] NOT NULL,
+	cadence SMALLINT[] NOT NULL,
+	temperature REAL[] NOT NULL
+);
+
+ALTER TABLE participation_segments ADD COLUMN avg_heart_rate DOUBLE PRECISION;
+ALTER TABLE participation_segments ADD COLUMN max_heart_rate SMALLINT;
+ALTER TABLE participation_segments ADD COLUMN avg_cadence DOUBLE PRECISION;
+ALTER TABLE participation_segments ADD COLUMN avg_temperature DOUBLE PRECISION;
@@ -0,0 +1,9 @@
+-- Elevation of every point of the segment line, NULL where the file had none
+ALTER TABLE segments ADD COLUMN elevations DOUBLE PRECISION[];
+
+-- Climbing statistics computed from the elevations, NULL for segments without elevation
+ALTER TABLE segments ADD COLUMN ascent DOUBLE PRECISION;
+ALTER TABLE segments ADD COLUMN descent DOUBLE PRECISION;
+ALTER TABLE segments ADD COLUMN min_elevation DOUBLE PRECISION;
+ALTER TABLE segments ADD COLUMN max_elevation DOUBLE PRECISION;
+ALTER TABLE segments ADD COLUMN avg_gradient DOUBLE PRECISION;
@@ -0,0 +1,10 @@
+-- Speed of every timed stage attempt, NULL for splits timed before these were computed
+ALTER TABLE participation_segments ADD COLUMN distance DOUBLE PRECISION;
+ALTER TABLE participation_segments ADD COLUMN avg_speed DOUBLE PRECISION;
+ALTER TABLE participation_segments ADD COLUMN max_speed DOUBLE PRECISION;
+ALTER TABLE participation_segments ADD COLUMN moving_seconds DOUBLE PRECISION;
+ALTER TABLE participation_segments ADD COLUMN stopped_seconds DOUBLE PRECISION;
+
+-- Seconds taken to reach each distance along the attempt
+ALTER TABLE participation_segments ADD COLUMN series_distance DOUBLE PRECISION[];
+ALTER TABLE participation_segments ADD COLUMN series_seconds DOUBLE PRECISION[];
@@ -0,0 +1,14 @@
+-- Split points dividing a segment into sectors, ordered by their distance from the start
+CREATE TABLE segment_sectors (
+	id BIGSERIAL PRIMARY KEY,
+	segment_id BIGINT NOT NULL REFERENCES segments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+	distance DOUBLE PRECISION NOT NULL,
+	name VARCHAR DEFAULT NULL,
+	geom GEOGRAPHY(POINT,4326) NOT NULL
+);
+
+CREATE INDEX segment_sectors_segment_id_idx ON segment_sectors (segment_id);
+
+-- Time through every sector of an attempt, NULL if the segment has no split points or one of
+-- them was missed
+ALTER TABLE participation_segments ADD COLUMN sector_seconds DOUBLE PRECISION[];
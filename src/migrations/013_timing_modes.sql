@@ -0,0 +1,9 @@
+ALTER TABLE events ADD COLUMN timing_mode VARCHAR NOT NULL DEFAULT 'stages';
+ALTER TABLE events ADD COLUMN mass_start TIMESTAMPTZ DEFAULT NULL;
+ALTER TABLE events ADD COLUMN late_penalty DOUBLE PRECISION NOT NULL DEFAULT 60;
+
+-- Seconds allowed to reach the start of a stage, NULL for no limit
+ALTER TABLE event_segments ADD COLUMN liaison_cutoff DOUBLE PRECISION DEFAULT NULL;
+
+-- Penalty included in the total time
+ALTER TABLE participations ADD COLUMN penalty_seconds DOUBLE PRECISION DEFAULT NULL;
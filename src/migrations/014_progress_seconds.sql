@@ -0,0 +1,3 @@
+-- Seconds from the start of the attempt at every point of its line, leaving out the time
+-- between chained parts. NULL for splits timed before these were stored.
+ALTER TABLE participation_segments ADD COLUMN point_seconds DOUBLE PRECISION[];
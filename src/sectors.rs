@@ -0,0 +1,199 @@
+//! Sectors split a segment at intermediate points, each attempt is timed through every sector
+//! between the start, the split points in order and the finish.
+
+use postgis::ewkb;
+use serde::{Deserialize, Serialize};
+
+use track::project;
+
+/// A split point along a segment.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SegmentSector {
+    pub id: i64,
+    /// Meters from the start of the segment
+    pub distance: f64,
+    pub name: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Where a split point is placed when adding it to a segment.
+#[derive(Debug, Clone, Copy)]
+pub enum SectorPosition {
+    /// Meters along the segment from its start
+    Distance(f64),
+    /// The point of the segment closest to a position
+    Point { lat: f64, lon: f64 },
+}
+
+/// The fastest time through a sector.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SectorLeader {
+    pub segment_id: i64,
+    /// Position of the sector in the segment, starting at 0
+    pub sector: usize,
+    pub participation_id: i64,
+    pub username: String,
+    pub elapsed: f64,
+}
+
+/// Distance in meters from a point to the line between two others, and the fraction along the
+/// line of the closest position.
+fn distance_to_line(point: &ewkb::Point, from: &ewkb::PointZ, to: &ewkb::PointZ) -> (f64, f64) {
+    let origin = (from.x, from.y);
+    let (ex, ey) = project(origin, (to.x, to.y));
+    let (px, py) = project(origin, (point.x, point.y));
+
+    let length = ex * ex + ey * ey;
+    let fraction = if length == 0.0 {
+        0.0
+    } else {
+        ((px * ex + py * ey) / length).max(0.0).min(1.0)
+    };
+    let dx = px - ex * fraction;
+    let dy = py - ey * fraction;
+    ((dx * dx + dy * dy).sqrt(), fraction)
+}
+
+/// A line of an attempt with the seconds from the start of the attempt at both ends.
+struct TimedLine<'a> {
+    from: &'a ewkb::PointZ,
+    to: &'a ewkb::PointZ,
+    start: f64,
+    end: f64,
+}
+
+/// The lines of an attempt chained from several pieces of a track, timed from its start. Like
+/// the elapsed time of the attempt, the time between the end of a piece and the start of the
+/// next is left out, so the sector times add up to it.
+fn timed_lines(pieces: &[ewkb::LineStringZ]) -> Vec<TimedLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0.0;
+    for piece in pieces {
+        let first = match piece.points.first() {
+            Some(first) => first.z,
+            None => continue,
+        };
+        for pair in piece.points.windows(2) {
+            lines.push(TimedLine {
+                from: &pair[0],
+                to: &pair[1],
+                start: offset + pair[0].z - first,
+                end: offset + pair[1].z - first,
+            });
+        }
+        offset += piece.points[piece.points.len() - 1].z - first;
+    }
+    lines
+}
+
+/// Find when an attempt passed a split point, searching its lines from `from_index` on. The
+/// first pass within `tolerance` counts, at the position where it came closest. Returns the index
+/// of the line it was passed on and the seconds from the start of the attempt.
+fn passing_time(
+    lines: &[TimedLine],
+    from_index: usize,
+    point: &ewkb::Point,
+    tolerance: f64,
+) -> Option<(usize, f64)> {
+    let mut closest: Option<(usize, f64, f64)> = None;
+    for (i, line) in lines.iter().enumerate().skip(from_index) {
+        let (distance, fraction) = distance_to_line(point, line.from, line.to);
+        if distance > tolerance {
+            if closest.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if closest.map_or(true, |(_, _, d)| distance < d) {
+            closest = Some((i, line.start + (line.end - line.start) * fraction, distance));
+        }
+    }
+
+    closest.map(|(i, time, _)| (i, time))
+}
+
+/// Time an attempt, given as the pieces of a track it was chained from, through every sector
+/// between the split points, given in order along the segment. Returns None if the attempt did
+/// not pass every split point within `tolerance`.
+pub fn sector_times(
+    pieces: &[ewkb::LineStringZ],
+    split_points: &[ewkb::Point],
+    tolerance: f64,
+) -> Option<Vec<f64>> {
+    let lines = timed_lines(pieces);
+    let finish = lines.last()?.end;
+
+    let mut times = Vec::with_capacity(split_points.len() + 1);
+    let mut index = 0;
+    let mut previous = 0.0;
+    for point in split_points {
+        let (i, time) = passing_time(&lines, index, point, tolerance)?;
+        times.push(time - previous);
+        index = i;
+        previous = time;
+    }
+    times.push(finish - previous);
+
+    Some(times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(points: &[(f64, f64)]) -> ewkb::LineStringZ {
+        ewkb::LineStringZ {
+            points: points
+                .iter()
+                .map(|&(x, z)| ewkb::PointZ {
+                    x,
+                    y: 45.0,
+                    z,
+                    srid: Some(4326),
+                })
+                .collect(),
+            srid: Some(4326),
+        }
+    }
+
+    fn split_point(x: f64) -> ewkb::Point {
+        ewkb::Point {
+            x,
+            y: 45.0,
+            srid: Some(4326),
+        }
+    }
+
+    #[test]
+    fn sectors_are_timed_at_the_split_points() {
+        let attempt = [piece(&[(6.0, 0.0), (6.001, 10.0), (6.002, 30.0)])];
+        let times = sector_times(&attempt, &[split_point(6.0015)], 5.0).unwrap();
+        assert_eq!(times.len(), 2);
+        assert!((times[0] - 20.0).abs() < 0.01);
+        assert!((times[1] - 10.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn gaps_between_pieces_are_left_out() {
+        // The rider stopped for 100 seconds between the two pieces of the attempt
+        let attempt = [
+            piece(&[(6.0, 0.0), (6.001, 10.0)]),
+            piece(&[(6.001, 110.0), (6.002, 130.0)]),
+        ];
+        let times = sector_times(&attempt, &[split_point(6.0015)], 5.0).unwrap();
+        let elapsed = attempt
+            .iter()
+            .map(|line| line.points[line.points.len() - 1].z - line.points[0].z)
+            .sum::<f64>();
+        assert!((times.iter().sum::<f64>() - elapsed).abs() < 0.01);
+        assert!((times[0] - 20.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn missed_split_point_gives_no_times() {
+        let attempt = [piece(&[(6.0, 0.0), (6.001, 10.0)])];
+        assert!(sector_times(&attempt, &[split_point(7.0)], 20.0).is_none());
+    }
+}
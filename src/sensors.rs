@@ -0,0 +1,93 @@
+//! Heart rate, cadence and temperature recorded along a ride. The readings are kept for every
+//! point of the uploaded track, so each timed stage attempt can be summarized without reading the
+//! file again.
+
+use serde::{Deserialize, Serialize};
+
+use track::TrackPoint;
+
+/// Sensor readings of a track, one entry per point. Points without a reading hold `None`.
+#[derive(Debug, Clone)]
+pub struct SensorSeries {
+    /// Seconds from the start of the track
+    pub seconds: Vec<f64>,
+    pub heart_rate: Vec<Option<i16>>,
+    pub cadence: Vec<Option<i16>>,
+    pub temperature: Vec<Option<f32>>,
+}
+
+/// Averages and maxima of the sensor readings during a part of a ride. Values are `None` when
+/// nothing was recorded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct SensorSummary {
+    pub avg_heart_rate: Option<f64>,
+    pub max_heart_rate: Option<i16>,
+    pub avg_cadence: Option<f64>,
+    pub avg_temperature: Option<f64>,
+}
+
+fn average<T: Copy + Into<f64>>(values: &[Option<T>]) -> Option<f64> {
+    let readings = values
+        .iter()
+        .filter_map(|v| *v)
+        .map(Into::into)
+        .collect::<Vec<f64>>();
+    if readings.is_empty() {
+        None
+    } else {
+        Some(readings.iter().sum::<f64>() / readings.len() as f64)
+    }
+}
+
+impl SensorSeries {
+    /// Collect the readings of a timed track. Returns None if the track has no readings at all,
+    /// so rides without sensors store nothing.
+    pub fn from_track(track: &[TrackPoint]) -> Option<SensorSeries> {
+        let start = track.first().and_then(|p| p.time)?;
+        if !track
+            .iter()
+            .any(|p| p.heart_rate.is_some() || p.cadence.is_some() || p.temperature.is_some())
+        {
+            return None;
+        }
+
+        let mut series = SensorSeries {
+            seconds: Vec::with_capacity(track.len()),
+            heart_rate: Vec::with_capacity(track.len()),
+            cadence: Vec::with_capacity(track.len()),
+            temperature: Vec::with_capacity(track.len()),
+        };
+        for point in track {
+            let time = match point.time {
+                Some(time) => time,
+                None => continue,
+            };
+            series
+                .seconds
+                .push((time - start).num_milliseconds() as f64 / 1000.0);
+            series.heart_rate.push(point.heart_rate.map(i16::from));
+            series.cadence.push(point.cadence.map(i16::from));
+            series.temperature.push(point.temperature.map(|t| t as f32));
+        }
+
+        Some(series)
+    }
+
+    /// Summarize the readings between two times, in seconds from the start of the track.
+    pub fn summarize(&self, start: f64, finish: f64) -> SensorSummary {
+        let first = self.seconds.iter().position(|&s| s >= start);
+        let last = self.seconds.iter().rposition(|&s| s <= finish);
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) if first <= last => (first, last),
+            _ => return SensorSummary::default(),
+        };
+
+        let heart_rate = &self.heart_rate[first..=last];
+        SensorSummary {
+            avg_heart_rate: average(heart_rate),
+            max_heart_rate: heart_rate.iter().filter_map(|v| *v).max(),
+            avg_cadence: average(&self.cadence[first..=last]),
+            avg_temperature: average(&self.temperature[first..=last]),
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Preprocessing of uploaded tracks before they are stored and matched. Recordings at 1 Hz hold
+//! far more points than needed to time a ride, these steps drop the redundant ones while keeping
+//! the position at any moment, interpolated between the remaining points, close to the original.
+//! Tracks are stored as uploaded unless simplification is asked for.
+
+use track::{project, TrackPoint};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SimplifyOptions {
+    /// Drop points with the same timestamp as the point before them
+    pub remove_duplicates: bool,
+    /// Drop points recorded while standing still within this many meters
+    pub stationary_radius: Option<f64>,
+    /// Maximum distance in meters between the simplified and the original position at any time
+    pub tolerance: Option<f64>,
+}
+
+/// Keep every point of the track.
+impl Default for SimplifyOptions {
+    fn default() -> SimplifyOptions {
+        SimplifyOptions {
+            remove_duplicates: false,
+            stationary_radius: None,
+            tolerance: None,
+        }
+    }
+}
+
+impl SimplifyOptions {
+    /// Apply every step, keeping positions within 2 meters of the original.
+    pub fn simplified() -> SimplifyOptions {
+        SimplifyOptions {
+            remove_duplicates: true,
+            stationary_radius: Some(2.0),
+            tolerance: Some(2.0),
+        }
+    }
+}
+
+/// Drop points recorded at the same time as the point preceding them, or at the same position
+/// when there are no timestamps.
+pub fn remove_duplicates(points: &[TrackPoint]) -> Vec<TrackPoint> {
+    let mut result: Vec<TrackPoint> = Vec::with_capacity(points.len());
+    for point in points {
+        let duplicate = match result.last() {
+            Some(last) => match (last.time, point.time) {
+                (Some(t1), Some(t2)) => t1 == t2,
+                _ => last.lat == point.lat && last.lon == point.lon,
+            },
+            None => false,
+        };
+        if !duplicate {
+            result.push(point.clone());
+        }
+    }
+    result
+}
+
+/// Replace every stop, a run of points within `radius` meters of where it began, by its first
+/// and last point so the time spent standing still is kept.
+pub fn remove_stationary(points: &[TrackPoint], radius: f64) -> Vec<TrackPoint> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let mut result = vec![points[0].clone()];
+    let mut anchor = 0;
+    for i in 1..points.len() {
+        if points[anchor].distance(&points[i]) <= radius {
+            continue;
+        }
+
+        if i - 1 > anchor {
+            result.push(points[i - 1].clone());
+        }
+        result.push(points[i].clone());
+        anchor = i;
+    }
+
+    if anchor != points.len() - 1 {
+        result.push(points[points.len() - 1].clone());
+    }
+    result
+}
+
+/// Distance in meters between a point and the position interpolated at its time on the line
+/// from `start` to `end`. Without timestamps this is the distance to the line.
+fn synchronized_distance(start: &TrackPoint, end: &TrackPoint, point: &TrackPoint) -> f64 {
+    let origin = (start.lon, start.lat);
+    let (ex, ey) = project(origin, (end.lon, end.lat));
+    let (px, py) = project(origin, (point.lon, point.lat));
+
+    let fraction = match (start.time, end.time, point.time) {
+        (Some(t1), Some(t2), Some(t)) if t2 > t1 => {
+            (t - t1).num_milliseconds() as f64 / (t2 - t1).num_milliseconds() as f64
+        }
+        _ => {
+            let length = ex * ex + ey * ey;
+            if length == 0.0 {
+                0.0
+            } else {
+                ((px * ex + py * ey) / length).max(0.0).min(1.0)
+            }
+        }
+    };
+
+    let dx = px - ex * fraction;
+    let dy = py - ey * fraction;
+    (dx * dx + dy * dy).sqrt()
+}
+
+/// Ramer-Douglas-Peucker simplification using the synchronized distance, so interpolating the
+/// time of any position along the simplified track stays within `tolerance` of the original.
+pub fn simplify_rdp(points: &[TrackPoint], tolerance: f64) -> Vec<TrackPoint> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let mut max_distance = 0.0;
+        let mut max_index = first;
+        for i in first + 1..last {
+            let distance = synchronized_distance(&points[first], &points[last], &points[i]);
+            if distance > max_distance {
+                max_distance = distance;
+                max_index = i;
+            }
+        }
+
+        if max_distance > tolerance {
+            keep[max_index] = true;
+            ranges.push((first, max_index));
+            ranges.push((max_index, last));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter(|&(_, keep)| keep)
+        .map(|(point, _)| point.clone())
+        .collect()
+}
+
+/// Apply all preprocessing steps enabled in the options.
+pub fn simplify_track(points: &[TrackPoint], options: &SimplifyOptions) -> Vec<TrackPoint> {
+    let mut result = if options.remove_duplicates {
+        remove_duplicates(points)
+    } else {
+        points.to_vec()
+    };
+
+    if let Some(radius) = options.stationary_radius {
+        result = remove_stationary(&result, radius);
+    }
+    if let Some(tolerance) = options.tolerance {
+        result = simplify_rdp(&result, tolerance);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+    use track::EARTH_RADIUS;
+
+    /// A point the given meters north and east of a fixed origin, recorded `seconds` after it.
+    fn point(north: f64, east: f64, seconds: i64) -> TrackPoint {
+        let meters_per_degree = EARTH_RADIUS.to_radians();
+        TrackPoint {
+            lat: 62.0 + north / meters_per_degree,
+            lon: 17.0 + east / (meters_per_degree * 62.0_f64.to_radians().cos()),
+            elevation: None,
+            time: Some(Utc.timestamp(1_500_000_000 + seconds, 0)),
+            heart_rate: None,
+            cadence: None,
+            temperature: None,
+        }
+    }
+
+    fn seconds(points: &[TrackPoint]) -> Vec<i64> {
+        points
+            .iter()
+            .map(|p| p.time.unwrap().timestamp() - 1_500_000_000)
+            .collect()
+    }
+
+    #[test]
+    fn points_at_the_same_time_are_dropped() {
+        let points = [point(0.0, 0.0, 0), point(1.0, 0.0, 0), point(2.0, 0.0, 1)];
+        assert_eq!(seconds(&remove_duplicates(&points)), vec![0, 1]);
+    }
+
+    #[test]
+    fn stops_keep_their_first_and_last_point() {
+        let points = [
+            point(0.0, 0.0, 0),
+            point(0.5, 0.0, 1),
+            point(1.0, 0.0, 2),
+            point(1.5, 0.0, 3),
+            point(50.0, 0.0, 4),
+            point(100.0, 0.0, 5),
+        ];
+        assert_eq!(seconds(&remove_stationary(&points, 2.0)), vec![0, 3, 4, 5]);
+    }
+
+    #[test]
+    fn steady_straight_ride_keeps_its_ends() {
+        let points = (0..11)
+            .map(|i| point(i as f64 * 10.0, 0.0, i))
+            .collect::<Vec<TrackPoint>>();
+        assert_eq!(seconds(&simplify_rdp(&points, 2.0)), vec![0, 10]);
+    }
+
+    #[test]
+    fn turns_and_speed_changes_are_kept() {
+        // Off the line at 5 seconds, and twice as fast after 7 seconds
+        let mut points = (0..8)
+            .map(|i| point(i as f64 * 10.0, 0.0, i))
+            .collect::<Vec<TrackPoint>>();
+        points[5] = point(50.0, 10.0, 5);
+        points.push(point(90.0, 0.0, 8));
+        points.push(point(110.0, 0.0, 9));
+
+        let simplified = simplify_rdp(&points, 2.0);
+        assert!(seconds(&simplified).contains(&5));
+        assert!(seconds(&simplified).contains(&7));
+    }
+
+    #[test]
+    fn default_options_keep_every_point() {
+        let points = [point(0.0, 0.0, 0), point(0.0, 0.0, 0), point(0.5, 0.0, 1)];
+        assert_eq!(
+            simplify_track(&points, &SimplifyOptions::default()).len(),
+            3
+        );
+        assert_eq!(
+            simplify_track(&points, &SimplifyOptions::simplified()).len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Speed and pace of a timed stage attempt, derived from the part of the ride matched to the
+//! stage. Distances are in meters, times in seconds and speeds in meters per second.
+
+use postgis::ewkb;
+use serde::{Deserialize, Serialize};
+
+use track::great_circle_distance;
+
+/// Below this speed the rider is taken to be standing still.
+pub const MOVING_SPEED: f64 = 0.5;
+
+/// The maximum speed is measured over at least this many seconds, single GPS fixes jump around
+/// too much to be trusted.
+pub const MAX_SPEED_INTERVAL: f64 = 5.0;
+
+/// Distance between the samples of the distance-time series.
+pub const SERIES_STEP: f64 = 25.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct SpeedStats {
+    /// Distance ridden, which can differ from the length of the segment
+    pub distance: f64,
+    pub avg_speed: f64,
+    pub max_speed: f64,
+    pub moving_seconds: f64,
+    pub stopped_seconds: f64,
+}
+
+/// Time taken to reach a distance along an attempt, both counted from its start.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct DistanceTime {
+    pub distance: f64,
+    pub seconds: f64,
+}
+
+/// Distance and time from the start of an attempt at every point of its line, with the time in
+/// the z coordinate as stored for participations.
+fn cumulative(line: &ewkb::LineStringZ) -> Vec<DistanceTime> {
+    let start = match line.points.first() {
+        Some(p) => p.z,
+        None => return Vec::new(),
+    };
+
+    let mut total = 0.0;
+    line.points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            if i > 0 {
+                let from = &line.points[i - 1];
+                total += great_circle_distance((from.x, from.y), (p.x, p.y));
+            }
+            DistanceTime {
+                distance: total,
+                seconds: p.z - start,
+            }
+        })
+        .collect()
+}
+
+/// Compute the speed statistics of an attempt.
+pub fn speed_stats(line: &ewkb::LineStringZ) -> SpeedStats {
+    let points = cumulative(line);
+    let last = match points.last() {
+        Some(last) => *last,
+        None => return SpeedStats::default(),
+    };
+
+    let mut stats = SpeedStats {
+        distance: last.distance,
+        ..SpeedStats::default()
+    };
+    if last.seconds > 0.0 {
+        stats.avg_speed = last.distance / last.seconds;
+    }
+
+    for pair in points.windows(2) {
+        let seconds = pair[1].seconds - pair[0].seconds;
+        let meters = pair[1].distance - pair[0].distance;
+        if seconds > 0.0 && meters / seconds >= MOVING_SPEED {
+            stats.moving_seconds += seconds;
+        } else {
+            stats.stopped_seconds += seconds;
+        }
+    }
+
+    // Extend a window from every point until it spans enough time
+    let mut end = 0;
+    for (i, from) in points.iter().enumerate() {
+        end = end.max(i);
+        while end < points.len() - 1 && points[end].seconds - from.seconds < MAX_SPEED_INTERVAL {
+            end += 1;
+        }
+        let seconds = points[end].seconds - from.seconds;
+        if seconds < MAX_SPEED_INTERVAL {
+            break;
+        }
+        stats.max_speed = stats
+            .max_speed
+            .max((points[end].distance - from.distance) / seconds);
+    }
+
+    stats
+}
+
+/// Sample the time taken along an attempt every `SERIES_STEP` meters, interpolating between the
+/// points of the line. The last sample is the end of the attempt.
+pub fn distance_time_series(line: &ewkb::LineStringZ) -> Vec<DistanceTime> {
+    let points = cumulative(line);
+    let last = match points.last() {
+        Some(last) => *last,
+        None => return Vec::new(),
+    };
+
+    let mut series = Vec::new();
+    let mut next = 0.0;
+    for pair in points.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        while next <= to.distance && next < last.distance {
+            let fraction = if to.distance > from.distance {
+                (next - from.distance) / (to.distance - from.distance)
+            } else {
+                0.0
+            };
+            series.push(DistanceTime {
+                distance: next,
+                seconds: from.seconds + (to.seconds - from.seconds) * fraction,
+            });
+            next += SERIES_STEP;
+        }
+    }
+    series.push(last);
+
+    series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use track::EARTH_RADIUS;
+
+    /// A line heading north along a meridian, given the meters from its start and the seconds at
+    /// every point.
+    fn line(points: &[(f64, f64)]) -> ewkb::LineStringZ {
+        let meters_per_degree = EARTH_RADIUS.to_radians();
+        ewkb::LineStringZ {
+            points: points
+                .iter()
+                .map(|&(meters, seconds)| ewkb::PointZ {
+                    x: 17.0,
+                    y: 62.0 + meters / meters_per_degree,
+                    z: seconds,
+                    srid: Some(4326),
+                })
+                .collect(),
+            srid: Some(4326),
+        }
+    }
+
+    #[test]
+    fn stopped_time_is_split_from_moving_time() {
+        let stats = speed_stats(&line(&[
+            (0.0, 0.0),
+            (50.0, 10.0),
+            (50.0, 40.0),
+            (100.0, 50.0),
+        ]));
+
+        assert!((stats.distance - 100.0).abs() < 0.5);
+        assert!((stats.avg_speed - 2.0).abs() < 0.01);
+        assert_eq!(stats.moving_seconds, 20.0);
+        assert_eq!(stats.stopped_seconds, 30.0);
+        assert!((stats.max_speed - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn series_is_sampled_every_step() {
+        let series = distance_time_series(&line(&[(0.0, 0.0), (110.0, 22.0)]));
+        assert_eq!(series.len(), 6);
+        assert_eq!(series[2].distance, 2.0 * SERIES_STEP);
+        assert!((series[2].seconds - 10.0).abs() < 0.1);
+        assert!((series[5].distance - 110.0).abs() < 0.5);
+        assert_eq!(series[5].seconds, 22.0);
+    }
+
+    #[test]
+    fn empty_line_has_no_stats() {
+        let empty = line(&[]);
+        assert_eq!(speed_stats(&empty).distance, 0.0);
+        assert!(distance_time_series(&empty).is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+//! Reader for Garmin Training Center (TCX) files, both activities and courses.
+
+use chrono::prelude::*;
+use xml::reader::{EventReader, XmlEvent};
+
+use track::TrackPoint;
+
+/// Check if the data looks like a TCX document.
+pub fn is_tcx(head: &str) -> bool {
+    head.contains("<TrainingCenterDatabase")
+}
+
+#[derive(Default)]
+struct PointBuilder {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    elevation: Option<f64>,
+    time: Option<DateTime<Utc>>,
+    heart_rate: Option<u8>,
+    cadence: Option<u8>,
+}
+
+/// Read all `<Track>` elements of a TCX file, each one becomes a separate list of points.
+pub fn read_tcx(data: &[u8]) -> Result<Vec<Vec<TrackPoint>>, String> {
+    let parser = EventReader::new(std::io::Cursor::new(data));
+
+    let mut path: Vec<String> = Vec::new();
+    let mut tracks: Vec<Vec<TrackPoint>> = Vec::new();
+    let mut point: Option<PointBuilder> = None;
+
+    for event in parser {
+        match event.map_err(|err| err.to_string())? {
+            XmlEvent::StartElement { name, .. } => {
+                match name.local_name.as_str() {
+                    "Track" => tracks.push(Vec::new()),
+                    "Trackpoint" => point = Some(PointBuilder::default()),
+                    _ => (),
+                }
+                path.push(name.local_name);
+            }
+            XmlEvent::EndElement { name } => {
+                path.pop();
+                if name.local_name != "Trackpoint" {
+                    continue;
+                }
+
+                // Trackpoints without a position are only sensor readings, skip them
+                if let Some(p) = point.take() {
+                    if let (Some(lat), Some(lon), Some(track)) = (p.lat, p.lon, tracks.last_mut()) {
+                        track.push(TrackPoint {
+                            lat,
+                            lon,
+                            elevation: p.elevation,
+                            time: p.time,
+                            heart_rate: p.heart_rate,
+                            cadence: p.cadence,
+                            temperature: None,
+                        });
+                    }
+                }
+            }
+            XmlEvent::Characters(text) => {
+                let p = match point {
+                    Some(ref mut p) => p,
+                    None => continue,
+                };
+
+                let text = text.trim();
+                let element = path.last().map(|s| s.as_str());
+                let parent = path.iter().rev().nth(1).map(|s| s.as_str());
+                match (parent, element) {
+                    (Some("Trackpoint"), Some("Time")) => {
+                        let time = DateTime::parse_from_rfc3339(text)
+                            .map_err(|err| format!("invalid time {}: {}", text, err))?;
+                        p.time = Some(time.with_timezone(&Utc));
+                    }
+                    (Some("Position"), Some("LatitudeDegrees")) => p.lat = text.parse().ok(),
+                    (Some("Position"), Some("LongitudeDegrees")) => p.lon = text.parse().ok(),
+                    (Some("Trackpoint"), Some("AltitudeMeters")) => p.elevation = text.parse().ok(),
+                    (Some("HeartRateBpm"), Some("Value")) => p.heart_rate = text.parse().ok(),
+                    (Some("Trackpoint"), Some("Cadence")) => p.cadence = text.parse().ok(),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use formats::read_track;
+    use std::fs;
+
+    fn fixture(path: &str) -> Vec<u8> {
+        fs::read(format!("{}/test_data/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap()
+    }
+
+    #[test]
+    fn fixture_holds_the_same_ride_as_gpx() {
+        let tracks = read_tcx(&fixture("Test Race 1 TCX/users/John Doe.tcx")).unwrap();
+        let gpx = read_track(&fixture("Test Race 1/users/John Doe.gpx")).unwrap();
+
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].len(), gpx.points.len());
+        for (point, expected) in tracks[0].iter().zip(&gpx.points) {
+            assert_eq!(point.time, expected.time);
+            assert!(point.distance(expected) < 1.0);
+            assert!(point.heart_rate.is_some());
+        }
+    }
+
+    #[test]
+    fn trackpoints_without_position_are_skipped() {
+        let data = br#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities><Activity Sport="Biking"><Lap><Track>
+    <Trackpoint>
+      <Time>2017-07-30T08:00:00Z</Time>
+      <HeartRateBpm><Value>120</Value></HeartRateBpm>
+    </Trackpoint>
+    <Trackpoint>
+      <Time>2017-07-30T08:00:01Z</Time>
+      <Position><LatitudeDegrees>62.5</LatitudeDegrees><LongitudeDegrees>17.9</LongitudeDegrees></Position>
+      <AltitudeMeters>12.5</AltitudeMeters>
+      <HeartRateBpm><Value>121</Value></HeartRateBpm>
+      <Cadence>80</Cadence>
+    </Trackpoint>
+  </Track></Lap></Activity></Activities>
+</TrainingCenterDatabase>"#;
+
+        let tracks = read_tcx(data).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].len(), 1);
+        let point = &tracks[0][0];
+        assert_eq!((point.lat, point.lon), (62.5, 17.9));
+        assert_eq!(point.elevation, Some(12.5));
+        assert_eq!(point.time, Some(Utc.ymd(2017, 7, 30).and_hms(8, 0, 1)));
+        assert_eq!(point.heart_rate, Some(121));
+        assert_eq!(point.cadence, Some(80));
+    }
+}
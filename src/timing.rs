@@ -0,0 +1,195 @@
+//! How the total time of an event is computed from the timed stages.
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Seconds added for every started minute a rider arrives late at a stage, unless the event says
+/// otherwise.
+pub const DEFAULT_LATE_PENALTY: f64 = 60.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimingMode {
+    /// The sum of the stage times, liaisons between stages are not timed
+    Stages,
+    /// Everything from the start, the mass start if the event has one, to the finish of the last
+    /// stage
+    Elapsed,
+    /// The sum of the stage times with penalties for arriving late at a stage after a liaison
+    Liaison,
+}
+
+impl Default for TimingMode {
+    fn default() -> TimingMode {
+        TimingMode::Stages
+    }
+}
+
+impl TimingMode {
+    pub fn from_name(name: &str) -> Option<TimingMode> {
+        match name {
+            "stages" => Some(TimingMode::Stages),
+            "elapsed" => Some(TimingMode::Elapsed),
+            "liaison" => Some(TimingMode::Liaison),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimingMode::Stages => "stages",
+            TimingMode::Elapsed => "elapsed",
+            TimingMode::Liaison => "liaison",
+        }
+    }
+}
+
+/// The timing settings of an event, settings left out when deserializing take their default.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct EventTiming {
+    pub mode: TimingMode,
+    /// The official start for every rider, otherwise each rider starts on their first stage
+    pub mass_start: Option<DateTime<Utc>>,
+    /// Seconds added for every started minute late at a stage
+    pub late_penalty: f64,
+}
+
+impl Default for EventTiming {
+    fn default() -> EventTiming {
+        EventTiming {
+            mode: TimingMode::default(),
+            mass_start: None,
+            late_penalty: DEFAULT_LATE_PENALTY,
+        }
+    }
+}
+
+/// The attempt picked for a stage, as used to compute the total.
+#[derive(Debug, Clone, Copy)]
+pub struct TimedStage {
+    pub elapsed: f64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Seconds allowed from the previous stage finish, or the mass start, to the start of this one
+    pub liaison_cutoff: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventTotal {
+    /// Total time including the penalty
+    pub elapsed: f64,
+    pub penalty: f64,
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_milliseconds() as f64 / 1000.0
+}
+
+/// Compute the total of a participation with every stage timed.
+pub fn compute_total(timing: &EventTiming, stages: &[TimedStage]) -> EventTotal {
+    let stage_time = stages.iter().map(|stage| stage.elapsed).sum::<f64>();
+
+    // Liaisons run between the stages in the order they were ridden
+    let mut ridden = stages.to_vec();
+    ridden.sort_by_key(|stage| stage.started_at);
+
+    match timing.mode {
+        TimingMode::Stages => EventTotal {
+            elapsed: stage_time,
+            penalty: 0.0,
+        },
+        TimingMode::Elapsed => {
+            let start = timing
+                .mass_start
+                .or_else(|| ridden.first().map(|stage| stage.started_at));
+            let finish = ridden.iter().map(|stage| stage.finished_at).max();
+            match (start, finish) {
+                (Some(start), Some(finish)) => EventTotal {
+                    elapsed: seconds_between(start, finish),
+                    penalty: 0.0,
+                },
+                _ => EventTotal::default(),
+            }
+        }
+        TimingMode::Liaison => {
+            let mut penalty = 0.0;
+            let mut previous_finish = timing.mass_start;
+            for stage in &ridden {
+                if let (Some(cutoff), Some(previous)) = (stage.liaison_cutoff, previous_finish) {
+                    let late = seconds_between(previous, stage.started_at) - cutoff;
+                    if late > 0.0 {
+                        penalty += (late / 60.0).ceil() * timing.late_penalty;
+                    }
+                }
+                previous_finish = Some(stage.finished_at);
+            }
+            EventTotal {
+                elapsed: stage_time + penalty,
+                penalty,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp(1_500_000_000 + seconds, 0)
+    }
+
+    fn stage(start: i64, finish: i64, liaison_cutoff: Option<f64>) -> TimedStage {
+        TimedStage {
+            elapsed: (finish - start) as f64,
+            started_at: at(start),
+            finished_at: at(finish),
+            liaison_cutoff,
+        }
+    }
+
+    #[test]
+    fn stages_mode_sums_stage_times() {
+        let timing = EventTiming::default();
+        let total = compute_total(&timing, &[stage(1000, 1100, None), stage(0, 200, None)]);
+        assert_eq!(total.elapsed, 300.0);
+        assert_eq!(total.penalty, 0.0);
+    }
+
+    #[test]
+    fn elapsed_mode_runs_from_the_first_start_or_the_mass_start() {
+        let stages = [stage(100, 200, None), stage(1000, 1100, None)];
+        let mut timing = EventTiming {
+            mode: TimingMode::Elapsed,
+            ..EventTiming::default()
+        };
+        assert_eq!(compute_total(&timing, &stages).elapsed, 1000.0);
+
+        timing.mass_start = Some(at(0));
+        assert_eq!(compute_total(&timing, &stages).elapsed, 1100.0);
+    }
+
+    #[test]
+    fn liaison_mode_penalizes_every_started_minute_late() {
+        let timing = EventTiming {
+            mode: TimingMode::Liaison,
+            mass_start: Some(at(0)),
+            late_penalty: 30.0,
+        };
+        // Two seconds late at the first stage, 61 seconds late at the second
+        let stages = [stage(602, 702, Some(600.0)), stage(1063, 1163, Some(300.0))];
+
+        let total = compute_total(&timing, &stages);
+        assert_eq!(total.penalty, 90.0);
+        assert_eq!(total.elapsed, 290.0);
+    }
+
+    #[test]
+    fn missing_settings_take_their_default() {
+        let timing: EventTiming = serde_json::from_str(r#"{"mode": "liaison"}"#).unwrap();
+        assert_eq!(timing.mode, TimingMode::Liaison);
+        assert_eq!(timing.late_penalty, DEFAULT_LATE_PENALTY);
+        assert!(timing.mass_start.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::prelude::*;
+use geo::algorithm::haversine_distance::HaversineDistance;
+
+use gpx_extensions::PointExtensions;
+
+/// Mean radius of the earth in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Distance in meters along the surface of the earth between two positions, given as longitude
+/// and latitude.
+pub fn great_circle_distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    geo::Point::new(from.0, from.1).haversine_distance(&geo::Point::new(to.0, to.1))
+}
+
+/// Position in meters east and north of an origin, both given as longitude and latitude.
+/// Accurate enough over the length of a single line of a track.
+pub fn project(origin: (f64, f64), point: (f64, f64)) -> (f64, f64) {
+    let x = (point.0 - origin.0).to_radians() * origin.1.to_radians().cos() * EARTH_RADIUS;
+    let y = (point.1 - origin.1).to_radians() * EARTH_RADIUS;
+    (x, y)
+}
+
+/// A single position of a recorded track, independent of the file format it was read from.
+#[derive(Clone, Debug)]
+pub struct TrackPoint {
+    pub lat: f64,
+    pub lon: f64,
+    pub elevation: Option<f64>,
+    pub time: Option<DateTime<Utc>>,
+    pub heart_rate: Option<u8>,
+    /// Pedal revolutions per minute
+    pub cadence: Option<u8>,
+    /// Air temperature in degrees Celsius
+    pub temperature: Option<f64>,
+}
+
+impl<'a> From<&'a gpx::Waypoint> for TrackPoint {
+    fn from(wp: &'a gpx::Waypoint) -> TrackPoint {
+        let p = wp.point();
+        TrackPoint {
+            lat: p.y(),
+            lon: p.x(),
+            elevation: wp.elevation,
+            time: wp.time,
+            heart_rate: None,
+            cadence: None,
+            temperature: None,
+        }
+    }
+}
+
+impl TrackPoint {
+    pub fn distance(&self, other: &TrackPoint) -> f64 {
+        great_circle_distance((self.lon, self.lat), (other.lon, other.lat))
+    }
+}
+
+/// A track with a name, e.g. one of the tracks written to an exported GPX file.
+#[derive(Debug)]
+pub struct NamedTrack {
+    pub name: String,
+    pub points: Vec<TrackPoint>,
+}
+
+/// Time between two consecutive points after which recording is considered paused.
+pub const GAP_THRESHOLD_SECONDS: f64 = 30.0;
+
+/// A break in recording, either between two source track segments or a pause inside one.
+#[derive(Debug)]
+pub struct TrackGap {
+    /// Index of the first point after the gap in the merged track.
+    pub index: usize,
+    pub seconds: f64,
+    pub meters: f64,
+    /// Set when the gap is at the boundary between two source track segments.
+    pub segment_boundary: bool,
+}
+
+#[derive(Debug)]
+pub struct MergedTrack {
+    pub points: Vec<TrackPoint>,
+    pub gaps: Vec<TrackGap>,
+}
+
+/// Merge a set of track segments into a single track.
+///
+/// Segments are ordered by their first timestamp, untimed segments keep their original order.
+/// Points of a segment that overlap in time with earlier segments are dropped, and every break in
+/// recording is reported in `gaps` so callers can decide how to treat it. Timestamps going
+/// backwards or repeating within a segment are kept for validation to report.
+pub fn merge_segments(mut segments: Vec<Vec<TrackPoint>>) -> MergedTrack {
+    segments.retain(|s| !s.is_empty());
+    if segments.iter().all(|s| s[0].time.is_some()) {
+        segments.sort_by_key(|s| s[0].time);
+    }
+
+    let mut points: Vec<TrackPoint> = Vec::new();
+    let mut gaps: Vec<TrackGap> = Vec::new();
+
+    for segment in segments {
+        // Latest time recorded by the segments merged so far
+        let merged_until = points.iter().filter_map(|p| p.time).max();
+
+        let mut first_in_segment = true;
+        for point in segment {
+            // Overlapping recordings, keep what we already have
+            if let (Some(until), Some(time)) = (merged_until, point.time) {
+                if time <= until {
+                    continue;
+                }
+            }
+
+            if let Some(last) = points.last() {
+                let seconds = match (last.time, point.time) {
+                    (Some(t1), Some(t2)) => {
+                        (t2.timestamp_millis() - t1.timestamp_millis()) as f64 / 1000.0
+                    }
+                    _ => 0.0,
+                };
+
+                if first_in_segment || seconds >= GAP_THRESHOLD_SECONDS {
+                    gaps.push(TrackGap {
+                        index: points.len(),
+                        seconds,
+                        meters: last.distance(&point),
+                        segment_boundary: first_in_segment,
+                    });
+                }
+            }
+
+            first_in_segment = false;
+            points.push(point);
+        }
+    }
+
+    MergedTrack { points, gaps }
+}
+
+/// Merge all tracks and track segments of a GPX file into a single track, adding the sensor
+/// readings from the point extensions, listed per track segment in the same order.
+pub fn merge_gpx_tracks(gpx: &gpx::Gpx, extensions: &[Vec<PointExtensions>]) -> MergedTrack {
+    let segments = gpx
+        .tracks
+        .iter()
+        .flat_map(|track| track.segments.iter())
+        .enumerate()
+        .map(|(i, segment)| {
+            segment
+                .points
+                .iter()
+                .enumerate()
+                .map(|(j, waypoint)| {
+                    let mut point = TrackPoint::from(waypoint);
+                    if let Some(extension) = extensions.get(i).and_then(|s| s.get(j)) {
+                        point.heart_rate = extension.heart_rate;
+                        point.cadence = extension.cadence;
+                        point.temperature = extension.temperature;
+                    }
+                    point
+                })
+                .collect()
+        })
+        .collect::<Vec<Vec<TrackPoint>>>();
+
+    merge_segments(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(seconds: i64) -> TrackPoint {
+        TrackPoint {
+            lat: 62.0 + seconds as f64 * 0.00001,
+            lon: 17.0,
+            elevation: None,
+            time: Some(Utc.timestamp(1_500_000_000 + seconds, 0)),
+            heart_rate: None,
+            cadence: None,
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn projection_agrees_with_great_circle_distance() {
+        let origin = (17.0, 62.0);
+        let point = (17.001, 62.001);
+        let (x, y) = project(origin, point);
+        let projected = (x * x + y * y).sqrt();
+        assert!((projected - great_circle_distance(origin, point)).abs() < 0.1);
+    }
+
+    #[test]
+    fn overlap_with_earlier_segment_is_dropped() {
+        let merged = merge_segments(vec![
+            vec![point(0), point(10), point(20)],
+            vec![point(15), point(25), point(30)],
+        ]);
+
+        let times = merged
+            .points
+            .iter()
+            .map(|p| p.time.unwrap().timestamp() - 1_500_000_000)
+            .collect::<Vec<i64>>();
+        assert_eq!(times, vec![0, 10, 20, 25, 30]);
+        assert_eq!(merged.gaps.len(), 1);
+        assert!(merged.gaps[0].segment_boundary);
+        assert_eq!(merged.gaps[0].index, 3);
+    }
+
+    #[test]
+    fn backwards_time_within_a_segment_is_kept() {
+        let merged = merge_segments(vec![vec![point(0), point(10), point(5), point(20)]]);
+        assert_eq!(merged.points.len(), 4);
+    }
+
+    #[test]
+    fn segments_are_ordered_by_time() {
+        let merged = merge_segments(vec![
+            vec![point(100), point(110)],
+            vec![point(0), point(10)],
+        ]);
+        assert_eq!(merged.points[0].time, point(0).time);
+        assert_eq!(merged.gaps[0].seconds, 90.0);
+    }
+}
@@ -0,0 +1,259 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+use track::TrackPoint;
+
+/// Highest speed we accept between two consecutive points, in meters per second.
+pub const MAX_SPEED: f64 = 30.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    EmptyTrack,
+    MissingTimestamp,
+    NonMonotonicTimestamp,
+    DuplicatePoint,
+    ImplausibleSpeed,
+    WrongDate,
+    OutsideWindow,
+}
+
+/// A problem found in a track. Issues of the same kind are reported once, pointing at the first
+/// offending point and counting the rest.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ValidationIssue {
+    pub kind: IssueKind,
+    pub severity: Severity,
+    pub index: Option<usize>,
+    pub count: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// A track is valid when nothing worse than warnings was found.
+    pub fn is_valid(&self) -> bool {
+        self.issues.iter().all(|i| i.severity != Severity::Error)
+    }
+
+    fn report(
+        &mut self,
+        kind: IssueKind,
+        severity: Severity,
+        index: Option<usize>,
+        message: String,
+    ) {
+        if let Some(issue) = self.issues.iter_mut().find(|i| i.kind == kind) {
+            issue.count += 1;
+            return;
+        }
+
+        self.issues.push(ValidationIssue {
+            kind,
+            severity,
+            index,
+            count: 1,
+            message,
+        });
+    }
+}
+
+/// Check a participation track before it is stored.
+///
+/// When `event_date` is given the track has to be recorded on that day. A day of slack is allowed
+/// on either side since timestamps are in UTC while the event date is local. When the event has a
+/// time window the track has to overlap it.
+pub fn validate_track(
+    track: &[TrackPoint],
+    event_date: Option<NaiveDate>,
+    window_open: Option<DateTime<Utc>>,
+    window_close: Option<DateTime<Utc>>,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    if track.len() < 2 {
+        report.report(
+            IssueKind::EmptyTrack,
+            Severity::Error,
+            None,
+            format!("track has {} points, at least 2 are needed", track.len()),
+        );
+        return report;
+    }
+
+    let mut last: Option<(&TrackPoint, DateTime<Utc>)> = None;
+    for (index, point) in track.iter().enumerate() {
+        let time = match point.time {
+            Some(time) => time,
+            None => {
+                report.report(
+                    IssueKind::MissingTimestamp,
+                    Severity::Error,
+                    Some(index),
+                    format!("point {} has no timestamp", index),
+                );
+                continue;
+            }
+        };
+
+        if let Some((last_point, last_time)) = last {
+            let seconds = (time.timestamp_millis() - last_time.timestamp_millis()) as f64 / 1000.0;
+            let meters = last_point.distance(point);
+
+            if seconds < 0.0 {
+                report.report(
+                    IssueKind::NonMonotonicTimestamp,
+                    Severity::Error,
+                    Some(index),
+                    format!("point {} is recorded before the point preceding it", index),
+                );
+            } else if seconds == 0.0 {
+                report.report(
+                    IssueKind::DuplicatePoint,
+                    Severity::Warning,
+                    Some(index),
+                    format!("point {} duplicates the point preceding it", index),
+                );
+            } else if meters / seconds > MAX_SPEED {
+                report.report(
+                    IssueKind::ImplausibleSpeed,
+                    Severity::Warning,
+                    Some(index),
+                    format!(
+                        "point {} is reached at {:.0} km/h",
+                        index,
+                        meters / seconds * 3.6
+                    ),
+                );
+            }
+        }
+
+        last = Some((point, time));
+    }
+
+    let times = track.iter().filter_map(|p| p.time);
+    let first = times.clone().min();
+    let last = times.max();
+
+    if let (Some(first), Some(last)) = (first, last) {
+        let before_window = window_open.map_or(false, |open| last < open);
+        let after_window = window_close.map_or(false, |close| first > close);
+        if before_window || after_window {
+            report.report(
+                IssueKind::OutsideWindow,
+                Severity::Error,
+                None,
+                format!(
+                    "track is recorded {} to {}, outside of the event time window",
+                    first, last
+                ),
+            );
+        }
+    }
+
+    if let Some(date) = event_date {
+        if let (Some(first), Some(last)) = (first, last) {
+            let first_date = first.date().naive_utc() - Duration::days(1);
+            let last_date = last.date().naive_utc() + Duration::days(1);
+            if date < first_date || date > last_date {
+                report.report(
+                    IssueKind::WrongDate,
+                    Severity::Error,
+                    None,
+                    format!(
+                        "track is recorded {} to {}, event is on {}",
+                        first.date().naive_utc(),
+                        last.date().naive_utc(),
+                        date
+                    ),
+                );
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use formats::read_track;
+
+    /// A GPX file with one track segment, moving about 11 meters north every point.
+    fn gpx_with_times(times: &[&str]) -> Vec<u8> {
+        let points = times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| {
+                format!(
+                    "<trkpt lat=\"{}\" lon=\"17.0\"><time>{}</time></trkpt>",
+                    62.0 + i as f64 * 0.0001,
+                    time
+                )
+            })
+            .collect::<Vec<String>>();
+        format!(
+            "<?xml version=\"1.0\"?>
+            <gpx version=\"1.1\" creator=\"test\" xmlns=\"http://www.topografix.com/GPX/1/1\">
+            <trk><trkseg>{}</trkseg></trk></gpx>",
+            points.join("")
+        )
+        .into_bytes()
+    }
+
+    fn kinds(report: &ValidationReport) -> Vec<IssueKind> {
+        report.issues.iter().map(|issue| issue.kind).collect()
+    }
+
+    #[test]
+    fn backwards_timestamp_is_an_error() {
+        let data = gpx_with_times(&[
+            "2017-07-30T08:00:00Z",
+            "2017-07-30T08:00:10Z",
+            "2017-07-30T08:00:05Z",
+            "2017-07-30T08:00:20Z",
+        ]);
+        let track = read_track(&data).unwrap();
+        assert_eq!(track.points.len(), 4);
+
+        let report = validate_track(&track.points, None, None, None);
+        assert_eq!(kinds(&report), vec![IssueKind::NonMonotonicTimestamp]);
+        assert_eq!(report.issues[0].index, Some(2));
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn repeated_timestamp_is_a_warning() {
+        let data = gpx_with_times(&[
+            "2017-07-30T08:00:00Z",
+            "2017-07-30T08:00:10Z",
+            "2017-07-30T08:00:10Z",
+            "2017-07-30T08:00:20Z",
+        ]);
+        let track = read_track(&data).unwrap();
+
+        let report = validate_track(&track.points, None, None, None);
+        assert_eq!(kinds(&report), vec![IssueKind::DuplicatePoint]);
+        assert!(report.is_valid());
+    }
+
+    #[test]
+    fn track_outside_of_the_window_is_an_error() {
+        let data = gpx_with_times(&["2017-07-30T08:00:00Z", "2017-07-30T08:00:10Z"]);
+        let track = read_track(&data).unwrap();
+        let open = Utc.ymd(2017, 7, 30).and_hms(9, 0, 0);
+
+        let report = validate_track(&track.points, None, Some(open), None);
+        assert_eq!(kinds(&report), vec![IssueKind::OutsideWindow]);
+    }
+}